@@ -0,0 +1,67 @@
+//! Compares updating objects one at a time with [`ExternalEditorApi::reload_incremental`]
+//! against a single [`ExternalEditorApi::reload_states`] that sends all scripts in one message.
+//!
+//! The stand-in for Tabletop Simulator answers immediately, so this measures the cost of
+//! serializing, sending and the round trips, but not the time the game takes to load scripts.
+//!
+//! Run with `cargo bench --bench reload_incremental`.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Instant;
+use tts_external_api::messages::ScriptState;
+use tts_external_api::{json, ExternalEditorApi, Value};
+
+fn main() -> io::Result<()> {
+    let receiver = TcpListener::bind("127.0.0.1:0")?;
+    let api = ExternalEditorApi::builder()
+        .listen_port(0)
+        .send_port(receiver.local_addr()?.port())
+        .build()?;
+    let answer_addr = api.listen_addr()?;
+    thread::spawn(move || respond(receiver, answer_addr));
+
+    for (objects, script_size) in [(10, 1_000), (100, 10_000), (500, 100_000)] {
+        let states: Vec<ScriptState> = (0..objects)
+            .map(|index| ScriptState {
+                name: None,
+                guid: format!("{index:06x}"),
+                script: "-".repeat(script_size),
+                ui: None,
+            })
+            .collect();
+
+        let start = Instant::now();
+        api.reload_states(states.clone())?;
+        let full = start.elapsed();
+
+        let start = Instant::now();
+        api.reload_incremental(states, |_, _| {})?;
+        let incremental = start.elapsed();
+
+        println!(
+            "{objects} objects with {script_size} byte scripts: full reload {full:?}, incremental {incremental:?}"
+        );
+    }
+    Ok(())
+}
+
+/// Stand-in for Tabletop Simulator that answers every execute with `true` and every reload with an empty reload
+fn respond(receiver: TcpListener, answer_addr: SocketAddr) -> io::Result<()> {
+    for stream in receiver.incoming() {
+        let mut buffer = String::new();
+        stream?.read_to_string(&mut buffer)?;
+        let message: Value = serde_json::from_str(&buffer)?;
+
+        let answer = match message["messageID"].as_u64() {
+            Some(1) => json!({"messageID": 1, "savePath": "", "scriptStates": []}),
+            Some(3) => {
+                json!({"messageID": 5, "returnID": message["returnID"], "returnValue": true})
+            }
+            _ => continue,
+        };
+        TcpStream::connect(answer_addr)?.write_all(answer.to_string().as_bytes())?;
+    }
+    Ok(())
+}
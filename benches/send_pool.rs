@@ -0,0 +1,56 @@
+//! Compares sending messages over a new connection each time, the way [`ExternalEditorApi::send`] does,
+//! with writing every message to a single reused connection.
+//!
+//! Reusing a connection is faster, but Tabletop Simulator reads a message until its connection
+//! gets closed, so only the first message of a reused connection would ever be processed.
+//! That's why the api doesn't keep a pool of send connections.
+//!
+//! Run with `cargo bench --bench send_pool`.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tts_external_api::messages::MessageCustomMessage;
+use tts_external_api::tcp::write_to;
+use tts_external_api::{json, ExternalEditorApi};
+
+/// Number of messages sent for each variant
+const MESSAGES: u32 = 2000;
+
+fn main() -> io::Result<()> {
+    // Stand-in for Tabletop Simulator that reads every connection until it gets closed
+    let receiver = TcpListener::bind("127.0.0.1:0")?;
+    let send_port = receiver.local_addr()?.port();
+    thread::spawn(move || {
+        for mut stream in receiver.incoming().flatten() {
+            thread::spawn(move || io::copy(&mut stream, &mut io::sink()));
+        }
+    });
+
+    let api = ExternalEditorApi::builder()
+        .listen_port(0)
+        .send_port(send_port)
+        .build()?;
+    let start = Instant::now();
+    for index in 0..MESSAGES {
+        api.custom_message(json!({ "index": index }))?;
+    }
+    report("new connection", start.elapsed());
+
+    let stream = TcpStream::connect(("127.0.0.1", send_port))?;
+    let start = Instant::now();
+    for index in 0..MESSAGES {
+        let message = MessageCustomMessage::new(json!({ "index": index }));
+        write_to(&stream, &message.as_message())?;
+    }
+    report("reused connection", start.elapsed());
+    Ok(())
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name}: {MESSAGES} messages in {elapsed:?} ({:?} per message)",
+        elapsed / MESSAGES
+    );
+}
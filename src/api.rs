@@ -0,0 +1,132 @@
+//! Trait abstracting the [`ExternalEditorApi`], so code built on it can be tested without the game
+
+use crate::messages::{Answer, AnswerReload, AnswerReturn};
+use crate::{ExternalEditorApi, Value};
+use std::io;
+
+/// The requests of the External Editor API, implemented by [`ExternalEditorApi`].
+///
+/// Tools can be generic over this trait and use a mock implementation in their tests,
+/// like the `MockApi` that is available with the `testing` feature.
+pub trait TtsApi {
+    /// Get a list containing the states for every object, see [`ExternalEditorApi::get_scripts`].
+    fn get_scripts(&self) -> io::Result<AnswerReload>;
+
+    /// Update the Lua scripts and UI XML for any objects listed in the message, see [`ExternalEditorApi::reload`].
+    fn reload(&self, script_states: Value) -> io::Result<AnswerReload>;
+
+    /// Send a custom message to the currently loaded game, see [`ExternalEditorApi::custom_message`].
+    fn custom_message(&self, message: Value) -> io::Result<()>;
+
+    /// Executes a lua script globally, see [`ExternalEditorApi::execute`].
+    fn execute(&self, script: String) -> io::Result<AnswerReturn>;
+
+    /// Reads the next incoming [`Answer`], see [`ExternalEditorApi::read`].
+    fn read(&self) -> io::Result<Answer>;
+}
+
+impl TtsApi for ExternalEditorApi {
+    fn get_scripts(&self) -> io::Result<AnswerReload> {
+        ExternalEditorApi::get_scripts(self)
+    }
+
+    fn reload(&self, script_states: Value) -> io::Result<AnswerReload> {
+        ExternalEditorApi::reload(self, script_states)
+    }
+
+    fn custom_message(&self, message: Value) -> io::Result<()> {
+        ExternalEditorApi::custom_message(self, message)
+    }
+
+    fn execute(&self, script: String) -> io::Result<AnswerReturn> {
+        ExternalEditorApi::execute(self, script)
+    }
+
+    fn read(&self) -> io::Result<Answer> {
+        Ok(ExternalEditorApi::read(self)?)
+    }
+}
+
+#[cfg(feature = "testing")]
+pub use mock::MockApi;
+
+#[cfg(feature = "testing")]
+mod mock {
+    use super::TtsApi;
+    use crate::messages::validate_script_states;
+    use crate::messages::*;
+    use crate::{Error, Value};
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Implementation of [`TtsApi`] that records the sent messages and replies with queued answers
+    #[derive(Debug, Default)]
+    pub struct MockApi {
+        sent: Mutex<Vec<Message>>,
+        answers: Mutex<VecDeque<Answer>>,
+    }
+
+    impl MockApi {
+        /// Creates a mock without any queued answers
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues an answer that gets returned by the next request expecting one.
+        /// Requests that don't expect an answer of that type return an [`Error::AnswerError`].
+        pub fn push_answer(&self, answer: Answer) {
+            self.answers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push_back(answer);
+        }
+
+        /// Takes the messages that have been sent since the last call, in order
+        pub fn take_sent(&self) -> Vec<Message> {
+            std::mem::take(&mut *self.sent.lock().unwrap_or_else(PoisonError::into_inner))
+        }
+
+        fn send(&self, message: impl Into<Message>) {
+            self.sent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(message.into());
+        }
+
+        fn next<T: TryFrom<Answer, Error = Error>>(&self) -> io::Result<T> {
+            Ok(T::try_from(self.read()?)?)
+        }
+    }
+
+    impl TtsApi for MockApi {
+        fn get_scripts(&self) -> io::Result<AnswerReload> {
+            self.send(MessageGetScripts::new());
+            self.next()
+        }
+
+        fn reload(&self, script_states: Value) -> io::Result<AnswerReload> {
+            validate_script_states(&script_states)?;
+            self.send(MessageReload::new_raw(script_states));
+            self.next()
+        }
+
+        fn custom_message(&self, message: Value) -> io::Result<()> {
+            self.send(MessageCustomMessage::new(message));
+            Ok(())
+        }
+
+        fn execute(&self, script: String) -> io::Result<AnswerReturn> {
+            self.send(MessageExecute::new(script));
+            self.next()
+        }
+
+        fn read(&self) -> io::Result<Answer> {
+            self.answers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop_front()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "no answer queued"))
+        }
+    }
+}
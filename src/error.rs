@@ -1,10 +1,86 @@
-use crate::messages::{Answer, Message};
+use crate::messages::{Answer, AnswerError, Message};
+use std::io;
+use std::string::FromUtf8Error;
+use std::time::Duration;
 use thiserror::Error;
 
+/// Errors that can occur when communicating with Tabletop Simulator
 #[derive(Error, Debug)]
 pub enum Error {
+    /// A [`Message`] was of a different type than expected
     #[error("message was of type {0:?}")]
     MessageError(Message),
+    /// An [`Answer`] was of a different type than expected
     #[error("answer was of type {0:?}")]
     AnswerError(Answer),
+    /// TTS sent an [`AnswerError`] while executing Lua code
+    #[error(transparent)]
+    LuaError(#[from] AnswerError),
+    /// No object with the guid exists in the loaded game
+    #[error("no object with guid {0:?} exists")]
+    ObjectNotFound(String),
+    /// The script states passed to [`ExternalEditorApi::reload`](crate::ExternalEditorApi::reload)
+    /// aren't an array of objects with a guid
+    #[error("invalid reload payload: {0}")]
+    InvalidReloadPayload(String),
+    /// The listen address and the send address are the same
+    #[error("the listen port and the send port are both {0}")]
+    SamePort(u16),
+    /// Writing a message to Tabletop Simulator took longer than the write timeout
+    #[error("sending the message timed out after {0:?}")]
+    SendTimeout(Duration),
+    /// No answer arrived within the read timeout set with
+    /// [`ExternalEditorApi::set_read_timeout`](crate::ExternalEditorApi::set_read_timeout)
+    #[error("no answer received within {0:?}")]
+    Timeout(Duration),
+    /// The listener can't accept connections anymore, e.g. because it has been closed or the process
+    /// ran out of file descriptors. Build a new [`ExternalEditorApi`](crate::ExternalEditorApi) to recover.
+    #[error("the listener can't accept connections anymore")]
+    ListenerClosed(#[source] io::Error),
+    /// TTS sent a message that isn't valid UTF-8. The raw bytes can be retrieved with
+    /// [`FromUtf8Error::as_bytes`] for debugging.
+    #[error("received a message that isn't valid UTF-8")]
+    InvalidUtf8(#[source] FromUtf8Error),
+    /// The api has been shut down by a [`ShutdownHandle`](crate::tcp::ShutdownHandle)
+    #[error("the api has been shut down")]
+    Shutdown,
+    /// TTS sent a message that isn't valid JSON or doesn't match any [`Answer`]
+    #[error("received a malformed answer")]
+    Json(#[source] serde_json::Error),
+    /// Reading from or writing to a connection failed
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+/// Wraps an [`io::Error`] in an [`Error::Io`], unless it already contains an [`enum@Error`],
+/// in which case that error gets returned.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = err
+                .into_inner()
+                .and_then(|inner| inner.downcast::<Error>().ok());
+            return *inner.expect("the inner error is an Error");
+        }
+        Error::Io(err)
+    }
+}
+
+/// Converts the error into an [`io::Error`], so it can be returned by functions that return an [`io::Result`].
+/// The original error can be retrieved with [`io::Error::into_inner`].
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Json(_) => io::ErrorKind::InvalidData,
+            Error::ObjectNotFound(_) => io::ErrorKind::NotFound,
+            Error::InvalidReloadPayload(_) | Error::SamePort(_) => io::ErrorKind::InvalidInput,
+            Error::SendTimeout(_) | Error::Timeout(_) => io::ErrorKind::TimedOut,
+            Error::InvalidUtf8(_) => io::ErrorKind::InvalidData,
+            Error::ListenerClosed(ref source) => source.kind(),
+            Error::Shutdown => io::ErrorKind::ConnectionAborted,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
 }
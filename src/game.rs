@@ -0,0 +1,155 @@
+//! Helpers for querying information about the loaded game and saving it
+
+use crate::messages::AnswerReload;
+use crate::tcp::ExternalEditorApi;
+use crate::{json, Value};
+use serde::Deserialize;
+use std::io;
+use std::path::PathBuf;
+
+/// Information about the loaded game, as set in the Game Info menu in Tabletop Simulator
+#[derive(Deserialize, Debug)]
+pub struct SaveInfo {
+    /// Name of the game
+    #[serde(rename = "name", default)]
+    pub name: String,
+    /// Type of the game, e.g. `"Card Game"`
+    #[serde(rename = "type", default)]
+    pub game_type: String,
+    /// Complexity of the game, e.g. `"Medium"`
+    #[serde(rename = "complexity", default)]
+    pub complexity: String,
+    /// Tags of the game
+    #[serde(rename = "tags", default)]
+    pub tags: Vec<String>,
+}
+
+/// The turn settings and the current turn of the loaded game, see [`Turns`](https://api.tabletopsimulator.com/turns/)
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TurnInfo {
+    /// Whether turns are enabled
+    #[serde(rename = "enable", default)]
+    pub enable: bool,
+    /// Player color whose turn it currently is, or an empty string if it's nobody's turn
+    #[serde(rename = "turn_color", default)]
+    pub turn_color: String,
+    /// Type of turn order, `1` for automatic and `2` for custom
+    #[serde(rename = "type", default)]
+    pub turn_type: u8,
+    /// Whether the turn order is reversed
+    #[serde(rename = "reverse_order", default)]
+    pub reverse_order: bool,
+    /// Player colors in the order of a custom turn order
+    #[serde(rename = "order", default)]
+    pub order: Vec<String>,
+}
+
+/// Information about where the loaded game came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModInfo {
+    /// Id of the Steam Workshop item, if the game was loaded from the Workshop
+    pub id: Option<String>,
+    /// Name of the game, as set in the Game Info menu
+    pub name: String,
+    /// Whether the game was loaded from the Steam Workshop
+    pub is_workshop: bool,
+}
+
+impl ExternalEditorApi {
+    /// Returns the [`SaveInfo`] of the loaded game.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    pub fn save_info(&self) -> io::Result<SaveInfo> {
+        // Empty tables get encoded as JSON objects, so tags are omitted if there are none
+        let script = String::from(
+            "return JSON.encode({\n\
+                name = Info.name,\n\
+                type = Info.type,\n\
+                complexity = Info.complexity,\n\
+                tags = Info.tags and #Info.tags > 0 and Info.tags or nil,\n\
+            })",
+        );
+
+        let return_value = self.execute(script)?.return_value;
+        Ok(serde_json::from_value(return_value)?)
+    }
+
+    /// Returns the [`TurnInfo`] of the loaded game.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    pub fn turn_info(&self) -> io::Result<TurnInfo> {
+        // Empty tables get encoded as JSON objects, so the order is omitted if it's empty
+        let script = String::from(
+            "return JSON.encode({\n\
+                enable = Turns.enable,\n\
+                turn_color = Turns.turn_color,\n\
+                type = Turns.type,\n\
+                reverse_order = Turns.reverse_order,\n\
+                order = Turns.order and #Turns.order > 0 and Turns.order or nil,\n\
+            })",
+        );
+
+        let return_value = self.execute(script)?.return_value;
+        Ok(serde_json::from_value(return_value)?)
+    }
+
+    /// Returns the [`ModInfo`] of the loaded game.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// The Lua API doesn't expose the Workshop id, so it's taken from the save path of the game,
+    /// which requires fetching the scripts of all objects.
+    pub fn mod_info(&self) -> io::Result<ModInfo> {
+        let save_path = self.get_scripts()?.save_path;
+        let name = match self.execute_raw_value(String::from("return Info.name"))? {
+            Value::String(name) => name,
+            _ => String::new(),
+        };
+
+        let id = save_path.as_deref().and_then(workshop_id);
+        Ok(ModInfo {
+            is_workshop: id.is_some(),
+            id,
+            name,
+        })
+    }
+
+    /// Saves the loaded game to its save file and reloads it, the same way pressing "Save & Play"
+    /// without changing any scripts does. Returns an [`AnswerReload`] message.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// The Lua API of Tabletop Simulator has no function to save the game, so this is the only way
+    /// to persist the current state, e.g. tags added with [`ExternalEditorApi::set_tags`].
+    /// TTS only sends an [`AnswerGameSaved`](crate::messages::AnswerGameSaved) message when the
+    /// player saves the game, not after this.
+    pub fn save_game(&self) -> io::Result<AnswerReload> {
+        // Objects not mentioned in the script states are not updated
+        self.reload(json!([]))
+    }
+}
+
+/// Returns the default folder Tabletop Simulator stores its saves in on the current platform,
+/// or `None` if the user's folders can't be determined.
+///
+/// This is `My Games/Tabletop Simulator/Saves` in the Documents folder on Windows,
+/// `Library/Tabletop Simulator/Saves` in the home directory on macOS and `Tabletop Simulator/Saves`
+/// in the data directory on Linux, usually `~/.local/share`. The folder isn't guaranteed to exist.
+pub fn default_saves_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        dirs::document_dir()?.join("My Games")
+    } else if cfg!(target_os = "macos") {
+        dirs::home_dir()?.join("Library")
+    } else {
+        dirs::data_dir()?
+    };
+    Some(base.join("Tabletop Simulator").join("Saves"))
+}
+
+/// Returns the Workshop id from the save path of a game that was loaded from the Steam Workshop.
+/// Workshop games are stored as `Mods/Workshop/<id>.json`.
+fn workshop_id(save_path: &str) -> Option<String> {
+    // The path uses the separators of the platform TTS runs on
+    let mut components = save_path.rsplit(['/', '\\']);
+    let id = components.next()?.strip_suffix(".json")?;
+    let is_workshop = components.next()? == "Workshop";
+
+    let is_id = !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_digit());
+    (is_workshop && is_id).then(|| id.to_string())
+}
@@ -10,35 +10,121 @@
 //! # Quick Start
 //!
 //! All messages are sent and received through the [`ExternalEditorApi`] struct.
-//! Creating the struct binds the TcpListener to port 39999. In order for messages to be
+//! Creating the struct binds the TcpListener to port 39998. In order for messages to be
 //! sent, a game has to be loaded inside Tabletop Simulator.
-//! ```
+//! ```no_run
 //! use tts_external_api::ExternalEditorApi;
 //!
 //! fn main() {
-//! 	let api = ExternalEditorApi::new();
+//!     let api = ExternalEditorApi::new();
 //!
 //!     api.execute(String::from("print('Hello World')"))
 //!         .expect("Can't connect. Is a save loaded?");
 //! }
 //! ```
+//!
+//! # Features
+//!
+//! - `arbitrary_precision`: Enables [`NumberMode::ArbitraryPrecision`](tcp::NumberMode::ArbitraryPrecision),
+//!   which keeps all digits of numbers in answers, using the feature of the same name of `serde_json`.
+//! - `testing`: Enables `api::MockApi`, an implementation of [`TtsApi`] for testing code without the game.
 
 #![deny(missing_docs)]
 
+pub mod api;
 mod error;
+pub mod game;
 pub mod messages;
+mod objects;
+pub mod prelude;
+pub mod run;
 pub mod tcp;
+pub mod types;
+pub mod util;
 
-pub use crate::tcp::ExternalEditorApi;
+pub use crate::api::TtsApi;
+pub use crate::error::Error;
+pub use crate::tcp::{ExternalEditorApi, ExternalEditorApiBuilder};
 pub use serde_json::{json, Value};
 
 /////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
-    use crate::{json, messages, ExternalEditorApi};
+    // Tests marked `#[ignore]` talk to a game loaded in Tabletop Simulator and can be run
+    // with `cargo test -- --ignored`. The crate doctest is `no_run` for the same reason.
+    // Everything else runs against `MockTts`.
+    use crate::{
+        game, json, messages, run, tcp, types, util, Error, ExternalEditorApi,
+        ExternalEditorApiBuilder, TtsApi, Value,
+    };
+    use std::collections::HashMap;
+    use std::io::{self, Read, Write};
+    use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    /// Stand-in for Tabletop Simulator that listens on the port messages get sent to
+    struct MockTts {
+        listener: TcpListener,
+        editor_addr: SocketAddr,
+    }
+
+    impl MockTts {
+        /// Builds an api and a mock that answers to it, both listening on free ports
+        fn new(builder: ExternalEditorApiBuilder) -> (Self, ExternalEditorApi) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let api = builder
+                .listen_port(0)
+                .send_port(listener.local_addr().unwrap().port())
+                .build()
+                .unwrap();
+            let tts = Self {
+                listener,
+                editor_addr: api.listen_addr().unwrap(),
+            };
+            (tts, api)
+        }
+
+        /// Sends an answer without waiting for a message first
+        fn answer(&self, answer: Value) {
+            send_answer(self.editor_addr, answer);
+        }
+
+        /// Receives the next message and sends back the answers returned by `respond`.
+        /// Return answers with the placeholder returnID 5 get the returnID of the message, like TTS echoes it.
+        fn respond<F>(&self, respond: F) -> JoinHandle<()>
+        where
+            F: FnOnce(Value) -> Vec<Value> + Send + 'static,
+        {
+            let listener = self.listener.try_clone().unwrap();
+            let editor_addr = self.editor_addr;
+            thread::spawn(move || {
+                let (mut stream, _addr) = listener.accept().unwrap();
+                let mut buffer = String::new();
+                stream.read_to_string(&mut buffer).unwrap();
+
+                let message: Value = serde_json::from_str(&buffer).unwrap();
+                let return_id = message.get("returnID").cloned();
+                for mut answer in respond(message) {
+                    if let (Some(return_id), Some(5)) = (&return_id, answer["returnID"].as_u64()) {
+                        answer["returnID"] = return_id.clone();
+                    }
+                    send_answer(editor_addr, answer);
+                }
+            })
+        }
+    }
+
+    fn send_answer(addr: SocketAddr, answer: Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(answer.to_string().as_bytes()).unwrap();
+    }
 
     #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_get_scripts() {
         let api = ExternalEditorApi::new();
 
@@ -47,6 +133,26 @@ mod tests {
     }
 
     #[test]
+    fn test_get_scripts_lua_only() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["messageID"], 0);
+            vec![json!({
+                "messageID": 1,
+                "savePath": "",
+                "scriptStates": [{"name": "Global", "guid": "-1", "script": "", "ui": "<Text/>"}]
+            })]
+        });
+
+        let states = api.get_scripts_lua_only().unwrap();
+        assert_eq!(states[0].guid, "-1");
+        assert_eq!(states[0].ui, None);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_reload() {
         let api = ExternalEditorApi::new();
 
@@ -55,6 +161,32 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
+    fn test_reload_states() {
+        let api = ExternalEditorApi::new();
+
+        let answer = api.reload_states(Vec::new()).unwrap();
+        println!("{:#?}", answer.script_states);
+    }
+
+    #[test]
+    fn test_script_state_omits_ui() {
+        let state = messages::ScriptState {
+            name: None,
+            guid: String::from("-1"),
+            script: String::from("print('Hello World')"),
+            ui: None,
+        };
+
+        let value = serde_json::to_value(state).unwrap();
+        assert_eq!(
+            value,
+            json!({"guid": "-1", "script": "print('Hello World')"})
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_custom_message() {
         let api = ExternalEditorApi::new();
 
@@ -62,6 +194,263 @@ mod tests {
     }
 
     #[test]
+    fn test_custom_message_round_trip() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        // Echo the message back the same way `onExternalMessage` calling `sendExternalMessage` would
+        let handle = tts.respond(|message| {
+            assert_eq!(message["messageID"], 2);
+            vec![json!({"messageID": 4, "customMessage": message["customMessage"]})]
+        });
+
+        api.custom_message(json![{"foo": "Foo"}]).unwrap();
+        let answer: messages::AnswerCustomMessage = api.wait().unwrap();
+        assert_eq!(answer.custom_message, json![{"foo": "Foo"}]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_request() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            vec![
+                json!({"messageID": 2, "message": "Foo"}),
+                json!({"messageID": 4, "customMessage": message["customMessage"]}),
+            ]
+        });
+        let answer: messages::AnswerCustomMessage = api
+            .request(messages::MessageCustomMessage::new(json!({"foo": "Foo"})))
+            .unwrap();
+        assert_eq!(answer.custom_message, json!({"foo": "Foo"}));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_try_new_port_in_use() {
+        // Keeps the default port occupied, unless another process already does
+        let _listener = TcpListener::bind((tcp::DEFAULT_IP, tcp::DEFAULT_LISTEN_PORT));
+
+        let err = ExternalEditorApi::try_new().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[test]
+    fn test_with_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let err = ExternalEditorApi::with_ports(port, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let api = ExternalEditorApi::with_ports(0, port).unwrap();
+        assert_ne!(api.listen_addr().unwrap().port(), port);
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new().max_message_size(16));
+
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+        let err = api.read_before(None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        api.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        let err = api.wait::<messages::AnswerReload>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<Error>()
+                .unwrap()
+                .as_ref(),
+            Error::Timeout(_)
+        ));
+
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+        assert!(api.read().is_ok());
+        assert!(api.set_read_timeout(Some(Duration::ZERO)).is_err());
+    }
+
+    #[test]
+    fn test_try_read() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        assert!(api.try_read().unwrap().is_none());
+
+        // The message is only complete once the connection gets closed
+        let mut stream = TcpStream::connect(tts.editor_addr).unwrap();
+        stream.write_all(b"{\"messageID\": 2, ").unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"\"message\": \"Hello World\"}").unwrap();
+        });
+
+        // Wait until the connection is pending
+        let answer = loop {
+            if let Some(answer) = api.try_read().unwrap() {
+                break answer;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(answer, messages::Answer::AnswerPrint(_)));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_incoming() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let handle = api.shutdown_handle().unwrap();
+
+        let mut stream = TcpStream::connect(tts.editor_addr).unwrap();
+        stream.write_all(b"{\"messageID\": 2, \"mess").unwrap();
+        drop(stream);
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+
+        let mut incoming = api.incoming();
+        assert!(incoming.next().unwrap().is_err());
+        assert!(incoming.next().unwrap().is_ok());
+        handle.shutdown();
+        assert!(incoming.next().is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_incoming_listener_closed() {
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+        let (_tts, mut api) = MockTts::new(ExternalEditorApiBuilder::new());
+        // Accepting on a socket that isn't listening fails the same way as on a closed listener
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        api.listener = unsafe { TcpListener::from_raw_fd(socket.into_raw_fd()) };
+
+        assert!(matches!(api.read(), Err(Error::ListenerClosed(_))));
+        assert!(api.incoming().next().is_none());
+    }
+
+    #[test]
+    fn test_read_malformed_json() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let mut stream = TcpStream::connect(tts.editor_addr).unwrap();
+        stream.write_all(b"{\"messageID\": 2, \"mess").unwrap();
+        drop(stream);
+        let err = api.read().unwrap_err();
+        assert!(matches!(err, Error::Json(_)));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+        // The listener keeps working after a malformed message
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+        assert!(api.read().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let mut stream = TcpStream::connect(tts.editor_addr).unwrap();
+        stream.write_all(b"{\"message\": \"\xff\"}").unwrap();
+        drop(stream);
+        let err = api.read_string().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        match err
+            .into_inner()
+            .unwrap()
+            .downcast::<Error>()
+            .unwrap()
+            .as_ref()
+        {
+            Error::InvalidUtf8(err) => assert_eq!(err.as_bytes(), b"{\"message\": \"\xff\"}"),
+            other => panic!("unexpected error {other:?}"),
+        }
+
+        // The listener keeps working after a malformed message
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+        assert!(api.read().is_ok());
+    }
+
+    #[test]
+    fn test_tts_api() {
+        fn global_value(api: &impl TtsApi) -> io::Result<Value> {
+            Ok(api.execute(String::from("return 1"))?.return_value)
+        }
+
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let handle =
+            tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": 1})]);
+        assert_eq!(global_value(&api).unwrap(), json!(1));
+        handle.join().unwrap();
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_mock_api() {
+        let api = crate::api::MockApi::new();
+        api.push_answer(messages::Answer::AnswerReturn(messages::AnswerReturn {
+            return_id: 5,
+            return_value: json!(1),
+            raw_return_value: json!(1),
+        }));
+
+        let answer = api.execute(String::from("return 1")).unwrap();
+        assert_eq!(answer.return_value, json!(1));
+        assert!(api.get_scripts().is_err());
+        let err = api.reload(json!([{"script": ""}])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let sent = api.take_sent();
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(sent[0], messages::Message::MessageExecute(_)));
+        assert!(matches!(sent[1], messages::Message::MessageGetScripts(_)));
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let path = std::env::temp_dir().join(format!("tts-trace-{}.jsonl", std::process::id()));
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new().trace_file(&path));
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 2, "message": "Foo"})]);
+        api.custom_message(json!({"foo": "Foo"})).unwrap();
+        api.read().unwrap();
+        handle.join().unwrap();
+        drop(api);
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["direction"], "sent");
+        assert_eq!(lines[0]["message"]["customMessage"], json!({"foo": "Foo"}));
+        assert_eq!(lines[1]["direction"], "received");
+        assert_eq!(lines[1]["message"]["message"], "Foo");
+    }
+
+    #[test]
+    fn test_send_timeout() {
+        let timeout = Duration::from_millis(100);
+        let (_tts, api) =
+            MockTts::new(ExternalEditorApiBuilder::new().write_timeout(Some(timeout)));
+
+        // The mock never accepts the connection, so the message fills up the socket buffers
+        let message = messages::MessageCustomMessage::new(json!("x".repeat(64 * 1024 * 1024)));
+        let err = api.send(message.as_message()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            err.into_inner().unwrap().downcast::<Error>().as_deref(),
+            Ok(Error::SendTimeout(duration)) if *duration == timeout
+        ));
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_execute() {
         let api = ExternalEditorApi::new();
 
@@ -72,19 +461,1399 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
+    fn test_wait_until_ready() {
+        let api = ExternalEditorApi::new();
+
+        api.wait_until_ready(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn test_has_script() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["messageID"], 3);
+            assert!(message["script"].as_str().unwrap().contains("\"db3f06\""));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+        });
+
+        assert!(api.has_script("db3f06").unwrap());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_has_script_object_not_found() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5})]);
+
+        let err = api.has_script("db3f06").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
+    fn test_execute_on_object_ensuring() {
+        let api = ExternalEditorApi::new();
+
+        let answer = api
+            .execute_on_object_ensuring(
+                String::from("return self.getName()"),
+                String::from("db3f06"),
+            )
+            .unwrap();
+        println!("{:#?}", answer);
+    }
+
+    #[test]
+    fn test_execute_lua_error() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            vec![
+                json!({"messageID": 2, "message": "Hello World"}),
+                json!({
+                    "messageID": 3,
+                    "error": "chunk_0:(1,0-4): attempt to call a nil value",
+                    "guid": "-1",
+                    "errorMessagePrefix": "Error in Global Script: "
+                }),
+            ]
+        });
+
+        let err = api.execute(String::from("foo()")).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*err, Error::LuaError(_)));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_run_retries_after_timeout() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        // Ignore the first attempt, then answer the second one
+        let first = tts.respond(|_| Vec::new());
+        let options = run::RunOptions::new()
+            .timeout(Duration::from_millis(200))
+            .retries(1);
+        let runner = thread::spawn(move || api.run(String::from("return 1"), options));
+        first.join().unwrap();
+        tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": 1})])
+            .join()
+            .unwrap();
+
+        assert_eq!(runner.join().unwrap().unwrap(), json!(1));
+    }
+
+    #[test]
+    fn test_run_not_connected() {
+        // Nothing listens on a port that was just freed
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let api = ExternalEditorApiBuilder::new()
+            .listen_port(0)
+            .send_port(port)
+            .build()
+            .unwrap();
+
+        let err = api.run(String::from("return 1"), run::RunOptions::new());
+        assert!(matches!(err, Err(run::RunError::NotConnected)));
+    }
+
+    #[test]
+    fn test_check_syntax() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert!(message["script"]
+                .as_str()
+                .unwrap()
+                .contains("load(\"return (\")"));
+            vec![json!({
+                "messageID": 5,
+                "returnID": 5,
+                "returnValue": "[string \"return (\"]:1: unexpected symbol near '<eof>'"
+            })]
+        });
+
+        let err = api.check_syntax("return (").unwrap();
+        assert!(err.unwrap().contains("unexpected symbol"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_check_syntax_json_error() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle =
+            tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": "42"})]);
+        assert_eq!(api.check_syntax("return (").unwrap().as_deref(), Some("42"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_tags() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            let tags = json!(["scripts/Foo.ttslua", "say \"hi\""]).to_string();
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": tags})]
+        });
+
+        let tags = api.get_tags("db3f06").unwrap();
+        assert_eq!(tags, vec!["scripts/Foo.ttslua", "say \"hi\""]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_tags_escapes_quotes() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            let script = message["script"].as_str().unwrap();
+            assert!(script.contains(r#"setTags({"scripts/Foo.ttslua", "say \"hi\""})"#));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+        });
+
+        let tags = [
+            String::from("scripts/Foo.ttslua"),
+            String::from("say \"hi\""),
+        ];
+        api.set_tags("db3f06", &tags).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_scripts() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let driver = thread::spawn(move || {
+            tts.respond(|message| {
+                let script = message["script"].as_str().unwrap();
+                assert!(script.contains(r#"ipairs({"-1", "db3f06"})"#));
+                let uis = json!({"uis": {"-1": "<Text/>", "db3f06": ""}}).to_string();
+                vec![json!({"messageID": 5, "returnID": 5, "returnValue": uis})]
+            })
+            .join()
+            .unwrap();
+            tts.respond(|message| {
+                assert_eq!(message["messageID"], 1);
+                assert_eq!(
+                    message["scriptStates"],
+                    json!([
+                        {"guid": "-1", "script": "print(1)", "ui": "<Text/>"},
+                        {"guid": "db3f06", "script": "print(2)"},
+                    ])
+                );
+                vec![json!({"messageID": 1, "scriptStates": []})]
+            })
+            .join()
+            .unwrap();
+        });
+
+        api.set_scripts(vec![
+            (String::from("-1"), String::from("print(1)")),
+            (String::from("db3f06"), String::from("print(2)")),
+        ])
+        .unwrap();
+        driver.join().unwrap();
+    }
+
+    #[test]
+    fn test_objects_with_tag() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            let script = message["script"].as_str().unwrap();
+            assert!(script.contains(r#"getObjectsWithTag("scripts/Foo.ttslua")"#));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": "{}"})]
+        });
+
+        let guids = api.objects_with_tag("scripts/Foo.ttslua").unwrap();
+        assert!(guids.is_empty());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_tags_batch() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            let script = message["script"].as_str().unwrap();
+            assert!(script.contains(r#"["db3f06"] = {"scripts/Foo.ttslua"}"#));
+            let missing = json!(["db3f06"]).to_string();
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": missing})]
+        });
+
+        let tags = HashMap::from([(
+            String::from("db3f06"),
+            vec![String::from("scripts/Foo.ttslua")],
+        )]);
+        let err = api.set_tags_batch(&tags).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        handle.join().unwrap();
+
+        // Nothing gets sent without any objects to update
+        api.set_tags_batch(&HashMap::new()).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
+    fn test_reload_ui_only() {
+        let api = ExternalEditorApi::new();
+
+        let answer = api
+            .reload_ui_only("-1", String::from("<Text>Hello World</Text>"))
+            .unwrap();
+        println!("{:#?}", answer.script_states);
+    }
+
+    #[test]
+    fn test_save_info() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            let info = json!({"name": "Chess", "type": "Strategy", "complexity": "Medium"});
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": info.to_string()})]
+        });
+
+        let info = api.save_info().unwrap();
+        assert_eq!(info.name, "Chess");
+        assert_eq!(info.game_type, "Strategy");
+        assert!(info.tags.is_empty());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_mod_info() {
+        for (save_path, id) in [
+            (
+                r"C:\Tabletop Simulator\Mods\Workshop\1234567890.json",
+                Some("1234567890"),
+            ),
+            ("/home/user/Tabletop Simulator/Saves/TS_Save_1.json", None),
+        ] {
+            let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+            // The name is sent as a string that is valid JSON and must stay a string
+            let driver = thread::spawn(move || {
+                let answer = json!({"messageID": 1, "savePath": save_path, "scriptStates": []});
+                tts.respond(move |_| vec![answer]).join().unwrap();
+                let answer = json!({"messageID": 5, "returnID": 5, "returnValue": "1942"});
+                tts.respond(move |_| vec![answer]).join().unwrap();
+            });
+
+            let info = api.mod_info().unwrap();
+            assert_eq!(info.id.as_deref(), id);
+            assert_eq!(info.is_workshop, id.is_some());
+            assert_eq!(info.name, "1942");
+            driver.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_turn_info() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            let info =
+                json!({"enable": true, "turn_color": "Red", "type": 2, "order": ["Red", "Blue"]});
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": info.to_string()})]
+        });
+
+        let info = api.turn_info().unwrap();
+        assert!(info.enable);
+        assert_eq!(info.turn_color, "Red");
+        assert_eq!(info.turn_type, 2);
+        assert!(!info.reverse_order);
+        assert_eq!(info.order, ["Red", "Blue"]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_reload_script_only() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let driver = thread::spawn(move || {
+            tts.respond(|_| {
+                let state = json!({"guid": "-1", "script": "print('Foo')", "ui": "<Text/>"});
+                vec![json!({"messageID": 5, "returnID": 5, "returnValue": state.to_string()})]
+            })
+            .join()
+            .unwrap();
+            tts.respond(|message| {
+                let state = json!({"guid": "-1", "script": "print('Bar')", "ui": "<Text/>"});
+                assert_eq!(message["scriptStates"], json!([state]));
+                vec![json!({"messageID": 1, "savePath": "", "scriptStates": [state]})]
+            })
+            .join()
+            .unwrap();
+        });
+
+        api.reload_script_only("-1", String::from("print('Bar')"))
+            .unwrap();
+        driver.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_locked() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert!(message["script"]
+                .as_str()
+                .unwrap()
+                .contains("setLock(true)"));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+        });
+        api.set_locked("db3f06", true).unwrap();
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5})]);
+        let err = api.set_locked("db3f06", false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_destroy_object() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert!(message["script"].as_str().unwrap().contains("destruct()"));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+        });
+        api.destroy_object("abcdef").unwrap();
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5})]);
+        let err = api.destroy_object("abcdef").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_find_objects_by_name() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert!(message["script"]
+                .as_str()
+                .unwrap()
+                .contains(r#"== "Chess \"Pawn\"""#));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": "[\"db3f06\",\"a0b2d5\"]"})]
+        });
+        let guids = api.find_objects_by_name("Chess \"Pawn\"").unwrap();
+        assert_eq!(guids, ["db3f06", "a0b2d5"]);
+        handle.join().unwrap();
+
+        let handle =
+            tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": "{}"})]);
+        assert!(api.find_objects_by_name("Foo").unwrap().is_empty());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_object_count() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["script"], "return #getAllObjects()");
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": 42})]
+        });
+        assert_eq!(api.object_count().unwrap(), 42);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_object_json() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            let object = json!({"GUID": "db3f06", "Name": "Chess_Pawn"});
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": object.to_string()})]
+        });
+        let object = api.get_object_json("db3f06").unwrap();
+        assert_eq!(object["Name"], "Chess_Pawn");
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5})]);
+        let err = api.get_object_json("db3f06").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_global_script() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        // Scripts that are valid JSON must not be deserialized
+        let handle = tts.respond(|message| {
+            assert_eq!(message["guid"], "-1");
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": "42"})]
+        });
+        assert_eq!(api.get_global_script().unwrap(), "42");
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5})]);
+        assert_eq!(api.get_global_ui().unwrap(), "");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_spawn_object() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert!(message["script"]
+                .as_str()
+                .unwrap()
+                .contains("spawnObjectJSON"));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": "abcdef"})]
+        });
+
+        let guid = api.spawn_object(json!({"Name": "BlockSquare"})).unwrap();
+        assert_eq!(guid, "abcdef");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_read_while_polling_with_deadline() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let api = Arc::new(api);
+
+        let poller = Arc::clone(&api);
+        let polling = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_millis(300);
+            poller.read_before(Some(deadline)).unwrap_err().kind()
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        let reader = Arc::clone(&api);
+        let reading = thread::spawn(move || reader.read());
+        assert_eq!(polling.join().unwrap(), io::ErrorKind::TimedOut);
+
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        assert!(matches!(
+            reading.join().unwrap(),
+            Ok(messages::Answer::AnswerPrint(_))
+        ));
+    }
+
+    #[test]
+    fn test_spawn_object_numeric_guid() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle =
+            tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": "104817"})]);
+
+        let guid = api.spawn_object(json!({"Name": "BlockSquare"})).unwrap();
+        assert_eq!(guid, "104817");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_object_state() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            let script = message["script"].as_str().unwrap();
+            assert!(script.contains(r#"object.memo = "{\"count\":3}""#));
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+        });
+        api.set_object_state("abcdef", json!({"count": 3})).unwrap();
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| {
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": {"memo": "{\"count\":3}"}})]
+        });
+        assert_eq!(api.get_object_state("abcdef").unwrap(), json!({"count": 3}));
+        handle.join().unwrap();
+
+        let handle = tts.respond(|_| {
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": {"memo": "plain text"}})]
+        });
+        assert_eq!(api.get_object_state("abcdef").unwrap(), json!("plain text"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_execute_raw_value() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle =
+            tts.respond(|_| vec![json!({"messageID": 5, "returnID": 5, "returnValue": "123"})]);
+        assert_eq!(
+            api.execute_raw_value("return '123'".to_string()).unwrap(),
+            json!("123")
+        );
+        handle.join().unwrap();
+
+        let answer =
+            tcp::read_from(&br#"{"messageID": 5, "returnID": 5, "returnValue": "123"}"#[..]);
+        let answer = messages::AnswerReturn::try_from(answer.unwrap()).unwrap();
+        assert_eq!(answer.return_value, json!(123));
+        assert_eq!(answer.raw_return_value, json!("123"));
+    }
+
+    #[test]
+    fn test_execute_file() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let path =
+            std::env::temp_dir().join(format!("tts-execute-file-{}.lua", std::process::id()));
+        std::fs::write(&path, "return 1 + 1").unwrap();
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["script"], "return 1 + 1");
+            vec![json!({"messageID": 5, "returnID": 5, "returnValue": 2})]
+        });
+        let answer = api.execute_file(&path).unwrap();
+        assert_eq!(answer.return_value, json!(2));
+        handle.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let error = api.execute_file(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_execute_return_id() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let driver = thread::spawn(move || {
+            let mut return_ids = Vec::new();
+            for _ in 0..2 {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                tts.respond(move |message| {
+                    sender.send(message["returnID"].clone()).unwrap();
+                    vec![
+                        // A late return of an earlier execute
+                        json!({"messageID": 5, "returnID": 0, "returnValue": false}),
+                        json!({"messageID": 5, "returnID": 5, "returnValue": true}),
+                    ]
+                })
+                .join()
+                .unwrap();
+                return_ids.push(receiver.recv().unwrap());
+            }
+            return_ids
+        });
+
+        for _ in 0..2 {
+            let answer = api.execute(String::from("return true")).unwrap();
+            assert_eq!(answer.return_value, json!(true));
+        }
+        let return_ids = driver.join().unwrap();
+        assert_ne!(return_ids[0], return_ids[1]);
+    }
+
+    #[test]
+    fn test_execute_with_return_id() {
+        const RETURN_ID: u64 = 1 << 40;
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["returnID"], RETURN_ID);
+            vec![
+                json!({"messageID": 5, "returnID": 1, "returnValue": false}),
+                json!({"messageID": 5, "returnID": RETURN_ID, "returnValue": true}),
+            ]
+        });
+        let answer = api
+            .execute_with_return_id(String::from("return true"), RETURN_ID)
+            .unwrap();
+        assert_eq!(answer.return_id, RETURN_ID);
+        assert_eq!(answer.return_value, json!(true));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_execute_typed() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let driver = thread::spawn(move || {
+            for point in [r#"{"x":1,"y":2}"#, r#"{"x":1}"#] {
+                tts.respond(move |_| {
+                    vec![json!({"messageID": 5, "returnID": 5, "returnValue": point})]
+                })
+                .join()
+                .unwrap();
+            }
+        });
+
+        let script = String::from("return JSON.encode({x = 1, y = 2})");
+        let point: Point = api.execute_typed(script.clone()).unwrap();
+        assert_eq!(point, Point { x: 1, y: 2 });
+        let err = api.execute_typed::<Point>(script).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        driver.join().unwrap();
+    }
+
+    #[test]
+    fn test_abandon() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        api.abandon(7);
+        tts.answer(json!({"messageID": 5, "returnID": 7, "returnValue": true}));
+        tts.answer(json!({"messageID": 2, "message": "Hello World"}));
+        assert!(matches!(
+            api.read().unwrap(),
+            messages::Answer::AnswerPrint(_)
+        ));
+
+        // Only the first return with the returnID gets discarded
+        tts.answer(json!({"messageID": 5, "returnID": 7, "returnValue": true}));
+        assert!(matches!(
+            api.read().unwrap(),
+            messages::Answer::AnswerReturn(_)
+        ));
+
+        // Returns that never arrive don't stay registered forever
+        for return_id in 0..=tcp::MAX_ABANDONED_RETURNS as u64 {
+            api.abandon(return_id);
+        }
+        tts.answer(json!({"messageID": 5, "returnID": 0, "returnValue": true}));
+        assert!(matches!(
+            api.read().unwrap(),
+            messages::Answer::AnswerReturn(_)
+        ));
+    }
+
+    #[test]
+    fn test_execute_with_prints() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            vec![
+                json!({"messageID": 2, "message": "Foo"}),
+                json!({"messageID": 4, "customMessage": {}}),
+                json!({"messageID": 2, "message": "Bar"}),
+                json!({"messageID": 5, "returnID": 5, "returnValue": true}),
+            ]
+        });
+        let (value, prints) = api.execute_with_prints("print('Foo')".to_string()).unwrap();
+        assert_eq!(value, json!(true));
+        assert_eq!(prints, ["Foo", "Bar"]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_reload_incremental() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let states = vec![
+            messages::ScriptState {
+                name: None,
+                guid: String::from("-1"),
+                script: String::from("print('Global')"),
+                ui: None,
+            },
+            messages::ScriptState {
+                name: None,
+                guid: String::from("db3f06"),
+                script: String::from("print('Pawn')"),
+                ui: Some(String::from("<Button/>")),
+            },
+        ];
+
+        let driver = thread::spawn(move || {
+            tts.respond(|message| {
+                assert!(message["script"]
+                    .as_str()
+                    .unwrap()
+                    .contains("Global.setLuaScript"));
+                vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+            })
+            .join()
+            .unwrap();
+            tts.respond(|message| {
+                assert!(message["script"].as_str().unwrap().contains("<Button/>"));
+                vec![json!({"messageID": 5, "returnID": 5, "returnValue": true})]
+            })
+            .join()
+            .unwrap();
+            tts.respond(|message| {
+                assert_eq!(message, json!({"messageID": 1, "scriptStates": []}));
+                vec![json!({"messageID": 1, "scriptStates": []})]
+            })
+            .join()
+            .unwrap();
+        });
+
+        let mut updates = Vec::new();
+        api.reload_incremental(states, |done, total| updates.push((done, total)))
+            .unwrap();
+        assert_eq!(updates, [(1, 2), (2, 2)]);
+        driver.join().unwrap();
+    }
+
+    #[test]
+    fn test_lua_string() {
+        assert_eq!(
+            crate::objects::lua_string("say \"hi\"\n\\"),
+            r#""say \"hi\"\n\\""#
+        );
+    }
+
+    #[test]
+    fn test_listen_on_free_port() {
+        let api = ExternalEditorApiBuilder::new()
+            .listen_port(0)
+            .build()
+            .unwrap();
+
+        assert_ne!(api.listen_addr().unwrap().port(), 0);
+    }
+
+    #[test]
+    fn test_listen_ip() {
+        let ip = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        // Skip on systems without IPv6 support
+        if TcpListener::bind((ip, 0)).is_err() {
+            return;
+        }
+
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new().listen_ip(ip));
+        assert_eq!(api.listen_addr().unwrap().ip(), ip);
+
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        let answer: messages::AnswerPrint = api.wait().unwrap();
+        assert_eq!(answer.message, "Foo");
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let api = Arc::new(
+            ExternalEditorApiBuilder::new()
+                .listen_port(0)
+                .build()
+                .unwrap(),
+        );
+        let handle = api.shutdown_handle().unwrap();
+
+        let reader = thread::spawn(move || api.read());
+        handle.shutdown();
+
+        let err = reader.join().unwrap().unwrap_err();
+        assert!(matches!(err, Error::Shutdown));
+        assert!(handle.is_shutdown());
+    }
+
+    #[test]
+    fn test_shutdown_unspecified_ip() {
+        let api = Arc::new(
+            ExternalEditorApiBuilder::new()
+                .listen_ip(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED))
+                .listen_port(0)
+                .build()
+                .unwrap(),
+        );
+        let handle = api.shutdown_handle().unwrap();
+        assert!(format!("{handle:?}").contains("127.0.0.1"));
+
+        let reader = thread::spawn(move || api.read());
+        handle.shutdown();
+        assert!(matches!(reader.join().unwrap(), Err(Error::Shutdown)));
+    }
+
+    #[test]
+    fn test_on_custom_message() {
+        #[derive(serde::Deserialize)]
+        struct Score {
+            player: String,
+            points: u32,
+        }
+
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let api = Arc::new(api);
+        let handle = api.shutdown_handle().unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let listener = thread::spawn({
+            let api = Arc::clone(&api);
+            move || api.on_custom_message(|score: Score| sender.send(score).unwrap())
+        });
+
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        tts.answer(json!({"messageID": 4, "customMessage": {"foo": "Foo"}}));
+        tts.answer(json!({"messageID": 4, "customMessage": {"player": "Red", "points": 3}}));
+
+        let score = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((score.player.as_str(), score.points), ("Red", 3));
+
+        handle.shutdown();
+        let err = listener.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn test_wait_with() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        tts.answer(json!({"messageID": 6}));
+        tts.answer(json!({"messageID": 7, "guid": "db3f06"}));
+
+        let mut skipped = Vec::new();
+        let answer: messages::AnswerObjectCreated = api
+            .wait_with(|answer| skipped.push(format!("{answer:?}")))
+            .unwrap();
+        assert_eq!(answer.guid, "db3f06");
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped[0].starts_with("AnswerPrint"));
+    }
+
+    #[test]
+    fn test_drain() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        assert!(api.drain().is_empty());
+
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        tts.answer(json!({"messageID": 2, "message": "Bar"}));
+        let answers = api.drain();
+        assert_eq!(answers.len(), 2);
+        assert!(
+            matches!(&answers[1], messages::Answer::AnswerPrint(answer) if answer.message == "Bar")
+        );
+        assert!(api.drain().is_empty());
+    }
+
+    #[test]
+    fn test_wait_deadline() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let deadline = Instant::now() + Duration::from_millis(300);
+
+        // A steady stream of prints must not keep the wait alive past its deadline
+        let flood = thread::spawn(move || {
+            while Instant::now() < deadline + Duration::from_millis(500) {
+                tts.answer(json!({"messageID": 2, "message": "Foo"}));
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let err = api
+            .wait_deadline::<messages::AnswerReturn>(deadline)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(Instant::now() < deadline + Duration::from_millis(400));
+        flood.join().unwrap();
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        assert_eq!(util::encode_binary(b"Foo"), json!("Rm9v"));
+        assert_eq!(util::encode_binary(b"Fo"), json!("Rm8="));
+        assert_eq!(util::encode_binary(b"F"), json!("Rg=="));
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..8 {
+            let encoded = util::encode_binary(&bytes[..len]);
+            assert_eq!(util::decode_binary(&encoded).unwrap(), &bytes[..len]);
+        }
+        let encoded = util::encode_binary(&bytes);
+        assert_eq!(util::decode_binary(&encoded).unwrap(), bytes);
+
+        for invalid in [
+            json!(1),
+            json!("Rm9"),
+            json!("Rm=v"),
+            json!("Rg==Rm9v"),
+            json!("R!9v"),
+        ] {
+            let err = util::decode_binary(&invalid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_wait_for_custom() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        tts.answer(json!({"messageID": 4, "customMessage": {"type": "ping"}}));
+        tts.answer(json!({"messageID": 2, "message": "Foo"}));
+        tts.answer(json!({"messageID": 4, "customMessage": {"type": "ready", "players": 2}}));
+
+        let payload = api
+            .wait_for_custom(|payload| payload["type"] == "ready")
+            .unwrap();
+        assert_eq!(payload["players"], 2);
+    }
+
+    #[test]
+    fn test_number_mode() {
+        let builder = ExternalEditorApiBuilder::new().listen_port(0);
+        let result = builder
+            .number_mode(tcp::NumberMode::ArbitraryPrecision)
+            .build();
+        if cfg!(not(feature = "arbitrary_precision")) {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            return;
+        }
+
+        let answer =
+            json!({"messageID": 5, "returnID": 5, "returnValue": "12345678901234567890123"});
+        for (mode, expected) in [
+            (tcp::NumberMode::Lossy, "1.2345678901234568e22"),
+            (
+                tcp::NumberMode::ArbitraryPrecision,
+                "12345678901234567890123",
+            ),
+        ] {
+            let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new().number_mode(mode));
+            tts.answer(answer.clone());
+            let answer: messages::AnswerReturn = api.wait().unwrap();
+            assert_eq!(answer.return_value.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_same_port() {
+        let err = ExternalEditorApiBuilder::new()
+            .listen_port(39999)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Listening on all interfaces also receives the messages sent to localhost
+        let err = ExternalEditorApiBuilder::new()
+            .listen_ip(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED))
+            .listen_port(39999)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_api_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ExternalEditorApi>();
+    }
+
+    #[test]
+    fn test_read_from_write_to() {
+        let mut buffer = Vec::new();
+        let message = messages::MessageCustomMessage::new(json!({"foo": "Foo"})).as_message();
+        tcp::write_to(&mut buffer, &message).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&buffer).unwrap(),
+            json!({"messageID": 2, "customMessage": {"foo": "Foo"}})
+        );
+
+        let answer = tcp::read_from(&br#"{"messageID": 2, "message": "Foo"}"#[..]).unwrap();
+        let answer = messages::AnswerPrint::try_from(answer).unwrap();
+        assert_eq!(answer.message, "Foo");
+    }
+
+    #[test]
+    fn test_script_states_keep_object_ui() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let states = json!([
+            {"name": "Global", "guid": "-1", "script": "", "ui": "<Text/>"},
+            {"name": "Chess Pawn", "guid": "db3f06", "script": "", "ui": "<Button/>"},
+        ]);
+
+        let answer = json!({"messageID": 1, "scriptStates": states});
+        let handle = tts.respond(move |_| vec![answer]);
+        let mut script_states = api.get_script_states().unwrap();
+        handle.join().unwrap();
+        script_states[1].script = String::from("print('Foo')");
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["scriptStates"][1]["ui"], "<Button/>");
+            assert_eq!(message["scriptStates"][1]["script"], "print('Foo')");
+            vec![json!({"messageID": 1, "scriptStates": []})]
+        });
+        api.reload_states(script_states).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_default_saves_dir() {
+        let Some(saves_dir) = game::default_saves_dir() else {
+            return;
+        };
+        assert!(saves_dir.ends_with("Tabletop Simulator/Saves"));
+        assert!(saves_dir.is_absolute());
+    }
+
+    #[test]
+    fn test_save_game() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message, json!({"messageID": 1, "scriptStates": []}));
+            vec![json!({"messageID": 1, "savePath": "C:\\TS_Save_1.json", "scriptStates": []})]
+        });
+        let answer = api.save_game().unwrap();
+        assert_eq!(answer.save_path.as_deref(), Some("C:\\TS_Save_1.json"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_reload_with_errors() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|_| {
+            let error = |guid| json!({"messageID": 3, "error": "Foo", "guid": guid, "errorMessagePrefix": ""});
+            vec![
+                error("-1"),
+                json!({"messageID": 1, "scriptStates": []}),
+                json!({"messageID": 2, "message": "Bar"}),
+                error("db3f06"),
+            ]
+        });
+        let (_reload, errors) = api
+            .reload_with_errors(json!([]), Duration::from_millis(200))
+            .unwrap();
+        let guids: Vec<_> = errors.iter().map(|error| error.guid.as_str()).collect();
+        assert_eq!(guids, ["-1", "db3f06"]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_reload_fire_and_forget() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["messageID"], 1);
+            Vec::new()
+        });
+        let answer = api
+            .reload_with_mode(json!([]), messages::ReloadMode::FireAndForget)
+            .unwrap();
+        assert!(answer.is_none());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_failed_reload_is_not_pending() {
+        // Nothing listens on the send port, so sending fails
+        let send_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let api = ExternalEditorApiBuilder::new()
+            .listen_port(0)
+            .send_port(send_port)
+            .serialize_during_reload(true)
+            .build()
+            .unwrap();
+
+        let result = api.reload_with_mode(json!([]), messages::ReloadMode::FireAndForget);
+        assert!(result.is_err());
+        // Would block forever waiting for the AnswerReload if the reload was still pending
+        assert!(api.custom_message(json!({})).is_err());
+    }
+
+    #[test]
+    fn test_serialize_during_reload() {
+        let (tts, api) =
+            MockTts::new(ExternalEditorApiBuilder::new().serialize_during_reload(true));
+        let api = Arc::new(api);
+
+        let handle = tts.respond(|_| Vec::new());
+        api.reload_with_mode(json!([]), messages::ReloadMode::FireAndForget)
+            .unwrap();
+        handle.join().unwrap();
+
+        let sender = thread::spawn({
+            let api = Arc::clone(&api);
+            move || api.custom_message(json!({"foo": "Foo"}))
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(!sender.is_finished());
+
+        let handle = tts.respond(|message| {
+            assert_eq!(message["messageID"], 2);
+            Vec::new()
+        });
+        tts.answer(json!({"messageID": 1, "scriptStates": []}));
+        sender.join().unwrap().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_script_state_save_object() {
+        let save = json!({
+            "SaveName": "Chess",
+            "LuaScript": "print('Global')",
+            "XmlUI": "",
+            "ObjectStates": [
+                {
+                    "GUID": "db3f06",
+                    "Name": "Chess_Pawn",
+                    "Nickname": "",
+                    "LuaScript": "print('Pawn')",
+                    "XmlUI": "<Button/>"
+                },
+                {"Name": "Custom_Model", "Transform": {}}
+            ]
+        });
+
+        let global = messages::ScriptState::from_save_object(&save).unwrap();
+        assert_eq!(global.guid, "-1");
+        assert_eq!(global.script, "print('Global')");
+        assert_eq!(global.ui, None);
+        assert_eq!(
+            global.to_save_object(),
+            json!({"LuaScript": "print('Global')", "XmlUI": ""})
+        );
+
+        // Global only round trips after merging into the save file
+        assert!(messages::ScriptState::from_save_object(&global.to_save_object()).is_none());
+        let mut merged = save.clone();
+        for (key, value) in global.to_save_object().as_object().unwrap() {
+            merged[key] = value.clone();
+        }
+        assert_eq!(
+            messages::ScriptState::from_save_object(&merged).as_ref(),
+            Some(&global)
+        );
+
+        let pawn = messages::ScriptState::from_save_object(&save["ObjectStates"][0]).unwrap();
+        assert_eq!(pawn.name.as_deref(), Some("Chess_Pawn"));
+        assert_eq!(pawn.ui.as_deref(), Some("<Button/>"));
+        assert_eq!(
+            messages::ScriptState::from_save_object(&pawn.to_save_object()).as_ref(),
+            Some(&pawn)
+        );
+
+        assert!(messages::ScriptState::from_save_object(&save["ObjectStates"][1]).is_none());
+    }
+
+    #[test]
+    fn test_reload_invalid_payload() {
+        let (_tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+
+        for payload in [json!({"guid": "-1"}), json!([1]), json!([{"script": ""}])] {
+            let err = api.reload(payload).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(matches!(
+                err.into_inner().unwrap().downcast::<Error>().as_deref(),
+                Ok(Error::InvalidReloadPayload(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reload_sends_payload_unchanged() {
+        let (tts, api) = MockTts::new(ExternalEditorApiBuilder::new());
+        let payload = json!([{"guid": "-1", "ui": "<Text/>", "foo": "Foo"}]);
+
+        let expected = payload.clone();
+        let handle = tts.respond(move |message| {
+            assert_eq!(message["scriptStates"], expected);
+            vec![json!({"messageID": 1, "savePath": "", "scriptStates": []})]
+        });
+        api.reload(payload).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_answer_reload_script_states() {
+        let answer = tcp::read_from(
+            &br#"{"messageID": 1, "scriptStates": [
+                {"name": "Global", "guid": "-1", "script": "print(1)", "ui": "<Text/>"},
+                {"name": "Chess Pawn", "guid": "db3f06"}
+            ]}"#[..],
+        )
+        .unwrap();
+
+        let messages::Answer::AnswerReload(answer) = answer else {
+            panic!("expected an AnswerReload");
+        };
+        assert_eq!(answer.script_states.len(), 2);
+        assert_eq!(answer.script_states[0].ui.as_deref(), Some("<Text/>"));
+        assert_eq!(answer.script_states[1].guid, "db3f06");
+        assert_eq!(answer.script_states[1].script, "");
+    }
+
+    #[test]
+    fn test_answer_error_display() {
+        let answer = messages::AnswerError {
+            error: String::from("chunk_0:(36,4-8): unexpected symbol near 'deck'"),
+            guid: String::from("-1"),
+            error_message_prefix: String::from("Error in Global Script: "),
+        };
+        assert_eq!(
+            answer.to_string(),
+            "Error in Global Script: chunk_0:(36,4-8): unexpected symbol near 'deck' (guid: -1)"
+        );
+
+        fn check(answer: messages::AnswerError) -> Result<(), Error> {
+            Err(answer)?
+        }
+        let err = check(answer).unwrap_err();
+        assert!(err.to_string().ends_with("(guid: -1)"));
+        assert!(matches!(err, Error::LuaError(_)));
+    }
+
+    #[test]
+    fn test_answer_reload_global() {
+        let answer = tcp::read_from(
+            &br#"{"messageID": 1, "scriptStates": [
+                {"name": "Chess Pawn", "guid": "db3f06", "script": "print(2)"},
+                {"name": "Global", "guid": "-1", "script": "print(\"hi\")\n", "ui": "<Text/>"}
+            ]}"#[..],
+        )
+        .unwrap();
+
+        let messages::Answer::AnswerReload(answer) = answer else {
+            panic!("expected an AnswerReload");
+        };
+        assert_eq!(answer.global_script().as_deref(), Some("print(\"hi\")\n"));
+        assert_eq!(answer.global_ui().as_deref(), Some("<Text/>"));
+
+        let answer = messages::AnswerReload {
+            save_path: None,
+            script_states: Vec::new(),
+        };
+        assert_eq!(answer.global_script(), None);
+        assert_eq!(answer.global_ui(), None);
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let messages = [
+            messages::MessageGetScripts::new().as_message(),
+            messages::MessageReload::new(vec![messages::ScriptState {
+                name: None,
+                guid: String::from("-1"),
+                script: String::new(),
+                ui: None,
+            }])
+            .as_message(),
+            messages::MessageReload::new_raw(json!([{"guid": "-1", "foo": "Foo"}])).as_message(),
+            messages::MessageCustomMessage::new(json!({"foo": "Foo"})).as_message(),
+            messages::MessageExecute::new_object("return 1".to_string(), "db3f06".to_string())
+                .as_message(),
+        ];
+
+        for message in messages {
+            let json = serde_json::to_value(&message).unwrap();
+            let replayed: messages::Message = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&replayed).unwrap(), json);
+        }
+
+        let error = serde_json::from_value::<messages::Message>(json!({"messageID": 9}));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn test_read_from_invalid_answer() {
+        for answer in [
+            &br#"{"messageID": 99}"#[..],
+            &br#"{"customMessage": {}}"#[..],
+            &br#"{"messageID": 7, "guid": 5}"#[..],
+        ] {
+            let error = tcp::read_from(answer).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let answer = tcp::read_from(&br#"{"messageID": 3, "error": "Foo", "guid": "-1"}"#[..]);
+        let answer = messages::AnswerError::try_from(answer.unwrap()).unwrap();
+        assert_eq!(answer.error_message_prefix, "");
+    }
+
+    #[test]
+    fn test_error_source_file() {
+        let answer = tcp::read_from(
+            &br#"{
+                "messageID": 3,
+                "error": "chunk_7:(36,4-8): unexpected symbol near 'deck'",
+                "guid": "db3f06",
+                "errorMessagePrefix": "Error in Script (Chess Pawn - db3f06): "
+            }"#[..],
+        )
+        .unwrap();
+        let answer = messages::AnswerError::try_from(answer).unwrap();
+        assert_eq!(answer.chunk(), Some(7));
+        assert_eq!(answer.line(), Some(36));
+
+        let files = HashMap::from([
+            (String::from("-1"), PathBuf::from("scripts/Global.ttslua")),
+            (String::from("db3f06"), PathBuf::from("scripts/Foo.ttslua")),
+        ]);
+        let state = |guid: &str, script: &str| messages::ScriptState {
+            name: None,
+            guid: guid.to_string(),
+            script: script.to_string(),
+            ui: None,
+        };
+        let mut states = vec![state("-1", "print('Global')"), state("a1b2c3", "")];
+        states.extend((1..7).map(|index| state(&format!("{index:06}"), "print(1)")));
+        states.push(state("db3f06", "print('Foo')"));
+
+        let mut sources = messages::ChunkSources::new();
+        sources.track(&states, &files);
+        assert_eq!(
+            answer.source_file(&sources),
+            Some(Path::new("scripts/Foo.ttslua"))
+        );
+        assert_eq!(sources.get(0), Some(Path::new("scripts/Global.ttslua")));
+        assert_eq!(sources.get(1), None);
+    }
+
+    #[test]
+    fn test_transform() {
+        let value = json!({
+            "position": {"x": 0.0, "y": 1.0, "z": 0.0},
+            "rotation": {"x": 0.0, "y": 180.0, "z": 0.0},
+            "scale": {"x": 1.0, "y": 1.0, "z": 1.0}
+        });
+
+        let transform: types::Transform = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(transform.rotation, types::Vec3::new(0.0, 180.0, 0.0));
+        assert_eq!(serde_json::to_value(transform).unwrap(), value);
+    }
+
+    #[test]
+    fn test_debug_truncates_scripts() {
+        let state = messages::ScriptState {
+            name: None,
+            guid: String::from("-1"),
+            script: "-".repeat(1000),
+            ui: None,
+        };
+
+        let debug = format!("{:?}", state);
+        assert!(debug.contains("…(1000 bytes)"));
+        assert!(debug.len() < 200);
+    }
+
+    #[test]
+    fn test_reload_without_save_path() {
+        let answer = tcp::read_from(&br#"{"messageID": 1, "scriptStates": []}"#[..]).unwrap();
+        let answer = messages::AnswerReload::try_from(answer).unwrap();
+        assert_eq!(answer.save_path, None);
+    }
+
+    #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_new_object() {
         let api = ExternalEditorApi::new();
 
-        let answer: messages::AnswerNewObject = api.wait();
+        let answer: messages::AnswerNewObject = api.wait().unwrap();
         println!("{:#?}", answer);
     }
 
     #[test]
+    #[ignore = "requires a running Tabletop Simulator instance"]
     fn test_read() {
         let api = ExternalEditorApi::new();
 
         loop {
-            let answer = api.read();
+            let answer = api.read().unwrap();
             println!("{:#?}", answer);
         }
     }
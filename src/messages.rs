@@ -1,9 +1,15 @@
 //! Incoming and Outgoing messages
 
 use crate::{error::Error, tcp::ExternalEditorApi, Value};
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::{__private::ser::FlatMapSerializer, ser::SerializeMap};
+use serde::{de, de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 use std::io::{self};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /////////////////////////////////////////////////////////////////////////////
 
@@ -62,10 +68,42 @@ impl Serialize for Message {
     }
 }
 
+impl<'de> serde::Deserialize<'de> for Message {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(d)?;
+        let message_id = value
+            .get("messageID")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| de::Error::missing_field("messageID"))?;
+
+        Ok(match message_id {
+            0 => Message::MessageGetScripts(
+                MessageGetScripts::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            1 => Message::MessageReload(
+                MessageReload::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            2 => Message::MessageCustomMessage(
+                MessageCustomMessage::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            3 => Message::MessageExecute(
+                MessageExecute::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            id => return Err(de::Error::custom(format!("unsupported messageID {id}"))),
+        })
+    }
+}
+
 /// Get a list containing the states for every object. Returns an [`AnswerReload`] message.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct MessageGetScripts {}
 
+impl From<MessageGetScripts> for Message {
+    fn from(message: MessageGetScripts) -> Self {
+        message.as_message()
+    }
+}
+
 impl TryFrom<Message> for MessageGetScripts {
     type Error = Error;
     fn try_from(message: Message) -> Result<Self, Self::Error> {
@@ -88,6 +126,14 @@ impl MessageGetScripts {
     }
 }
 
+/// Constructs a new Get Lua Scripts Message.
+/// This is functionally the same as using `MessageGetScripts::new()`.
+impl Default for MessageGetScripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Update the Lua scripts and UI XML for any objects listed in the message,
 /// and then reloads the save file, the same way it does when pressing "Save & Play" within the in-game editor.
 /// Returns an [`AnswerReload`] message.
@@ -95,11 +141,29 @@ impl MessageGetScripts {
 /// Any objects mentioned have both their Lua script and their UI XML updated.
 /// If no value is set for either the "script" or "ui" key then the
 /// corresponding Lua script or UI XML is deleted.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct MessageReload {
     /// Contains a list objects and their state
     #[serde(rename = "scriptStates")]
-    pub script_states: Value,
+    pub script_states: ReloadStates,
+}
+
+/// The script states of a [`MessageReload`]
+///
+/// Deserializing always produces [`ReloadStates::Raw`], so no keys get lost when replaying messages.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ReloadStates {
+    /// Script states that get sent unchanged, see [`ExternalEditorApi::reload`]
+    Raw(Value),
+    /// Typed script states, see [`ExternalEditorApi::reload_states`]
+    States(Vec<ScriptState>),
+}
+
+impl From<MessageReload> for Message {
+    fn from(message: MessageReload) -> Self {
+        message.as_message()
+    }
 }
 
 impl TryFrom<Message> for MessageReload {
@@ -114,8 +178,17 @@ impl TryFrom<Message> for MessageReload {
 
 impl MessageReload {
     /// Constructs a new Save & Play Message
-    pub fn new(script_states: Value) -> Self {
-        Self { script_states }
+    pub fn new(script_states: Vec<ScriptState>) -> Self {
+        Self {
+            script_states: ReloadStates::States(script_states),
+        }
+    }
+
+    /// Constructs a new Save & Play Message that sends `script_states` as is
+    pub fn new_raw(script_states: Value) -> Self {
+        Self {
+            script_states: ReloadStates::Raw(script_states),
+        }
     }
 
     /// Returns self as [`Message::MessageReload`]
@@ -124,17 +197,126 @@ impl MessageReload {
     }
 }
 
+/// The Lua script and UI XML of a single object, as used in the `scriptStates` of [`MessageReload`].
+///
+/// Omitting the UI with `ui: None` also omits the key when serializing,
+/// which deletes the UI XML of the object when reloading.
+///
+/// # Example
+/// ```json
+/// {
+///     "name": "Global",
+///     "guid": "-1",
+///     "script": "...",
+///     "ui": "..."
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ScriptState {
+    /// Name of the object
+    #[serde(rename = "name", skip_serializing_if = "Option::is_none", default)]
+    pub name: Option<String>,
+    /// Guid of the object
+    #[serde(rename = "guid")]
+    pub guid: String,
+    /// Lua script of the object
+    #[serde(rename = "script", default)]
+    pub script: String,
+    /// UI XML of the object
+    #[serde(rename = "ui", skip_serializing_if = "Option::is_none", default)]
+    pub ui: Option<String>,
+}
+
+impl ScriptState {
+    /// Creates a script state from an object in the `ObjectStates` of a save file,
+    /// or for Global from the save file itself. Returns `None` if the value is neither.
+    ///
+    /// Save files store the script in `LuaScript` and the UI XML in `XmlUI`. The name is taken
+    /// from `Nickname`, or from `Name` if the object has no nickname.
+    pub fn from_save_object(value: &Value) -> Option<Self> {
+        let field = |key| {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .filter(|field| !field.is_empty())
+        };
+
+        let (guid, name) = match field("GUID") {
+            Some(guid) => (guid, field("Nickname").or(field("Name"))),
+            None if value.get("ObjectStates").is_some() => ("-1", Some("Global")),
+            None => return None,
+        };
+        Some(Self {
+            name: name.map(String::from),
+            guid: guid.to_string(),
+            script: field("LuaScript").unwrap_or_default().to_string(),
+            ui: field("XmlUI").map(String::from),
+        })
+    }
+
+    /// Returns the script state in the format of an object in the `ObjectStates` of a save file.
+    /// For objects this is the inverse of [`ScriptState::from_save_object`].
+    ///
+    /// Only the `GUID`, `Nickname`, `LuaScript` and `XmlUI` fields get set, so merge them into the
+    /// existing object when editing a save file. For Global only `LuaScript` and `XmlUI` get set,
+    /// which have to be merged into the save file itself. The result alone has no `ObjectStates`,
+    /// so [`ScriptState::from_save_object`] only recognizes it as Global after merging.
+    pub fn to_save_object(&self) -> Value {
+        let mut object = serde_json::Map::new();
+        if self.guid != "-1" {
+            object.insert("GUID".into(), self.guid.clone().into());
+            if let Some(name) = &self.name {
+                object.insert("Nickname".into(), name.clone().into());
+            }
+        }
+        object.insert("LuaScript".into(), self.script.clone().into());
+        object.insert("XmlUI".into(), self.ui.clone().unwrap_or_default().into());
+        Value::Object(object)
+    }
+}
+
+/// Truncates the script and UI XML to keep the output readable.
+/// Use the fields directly to get the full content.
+impl fmt::Debug for ScriptState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptState")
+            .field("name", &self.name)
+            .field("guid", &self.guid)
+            .field("script", &Preview(&self.script))
+            .field("ui", &self.ui.as_deref().map(Preview))
+            .finish()
+    }
+}
+
+/// Whether [`ExternalEditorApi::reload_with_mode`] waits for the [`AnswerReload`] of the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReloadMode {
+    /// Wait until TTS has reloaded the game and sent the script states of all objects.
+    /// This can take a while for big saves.
+    #[default]
+    WaitFull,
+    /// Return as soon as the message has been sent. The [`AnswerReload`] still arrives later
+    /// and gets received by the next read.
+    FireAndForget,
+}
+
 /// Send a custom message to be forwarded to the `onExternalMessage` event handler
 /// in the currently loaded game. The value of customMessage must be an object,
 /// and is passed as a parameter to the event handler.
 /// If this value is not an object then the event is not triggered.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct MessageCustomMessage {
     /// Custom message that gets forwarded
     #[serde(rename = "customMessage")]
     pub custom_message: Value,
 }
 
+impl From<MessageCustomMessage> for Message {
+    fn from(message: MessageCustomMessage) -> Self {
+        message.as_message()
+    }
+}
+
 impl TryFrom<Message> for MessageCustomMessage {
     type Error = Error;
     fn try_from(message: Message) -> Result<Self, Self::Error> {
@@ -159,7 +341,7 @@ impl MessageCustomMessage {
 
 /// Executes a lua script and returns the value in a [`AnswerReturn`] message.
 /// Using a guid of "-1" runs the script globally.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct MessageExecute {
     /// Return Id of the execute message
     #[serde(rename = "returnID")]
@@ -172,6 +354,12 @@ pub struct MessageExecute {
     pub script: String,
 }
 
+impl From<MessageExecute> for Message {
+    fn from(message: MessageExecute) -> Self {
+        message.as_message()
+    }
+}
+
 impl TryFrom<Message> for MessageExecute {
     type Error = Error;
     fn try_from(message: Message) -> Result<Self, Self::Error> {
@@ -183,7 +371,10 @@ impl TryFrom<Message> for MessageExecute {
 }
 
 impl MessageExecute {
-    /// Constructs a new Execute Lua Code Message that executes code globally
+    /// Constructs a new Execute Lua Code Message that executes code globally.
+    ///
+    /// The message uses the returnID 5. The execute functions of [`ExternalEditorApi`] replace it
+    /// with a unique returnID, to match the [`AnswerReturn`] to the message.
     pub fn new(script: String) -> Self {
         Self {
             return_id: 5,
@@ -192,7 +383,7 @@ impl MessageExecute {
         }
     }
 
-    /// Constructs a new Execute Lua Code Message that executes code on an object
+    /// Constructs a new Execute Lua Code Message that executes code on an object, using the returnID 5
     pub fn new_object(script: String, guid: String) -> Self {
         Self {
             return_id: 5,
@@ -257,20 +448,30 @@ pub enum Answer {
 impl<'de> serde::Deserialize<'de> for Answer {
     fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let value = Value::deserialize(d)?;
+        let message_id = value
+            .get("messageID")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| de::Error::missing_field("messageID"))?;
 
-        Ok(
-            match value.get("messageID").and_then(Value::as_u64).unwrap() {
-                0 => Answer::AnswerNewObject(AnswerNewObject::deserialize(value).unwrap()),
-                1 => Answer::AnswerReload(AnswerReload::deserialize(value).unwrap()),
-                2 => Answer::AnswerPrint(AnswerPrint::deserialize(value).unwrap()),
-                3 => Answer::AnswerError(AnswerError::deserialize(value).unwrap()),
-                4 => Answer::AnswerCustomMessage(AnswerCustomMessage::deserialize(value).unwrap()),
-                5 => Answer::AnswerReturn(AnswerReturn::deserialize(value).unwrap()),
-                6 => Answer::AnswerGameSaved(AnswerGameSaved::deserialize(value).unwrap()),
-                7 => Answer::AnswerObjectCreated(AnswerObjectCreated::deserialize(value).unwrap()),
-                id_ => panic!("unsupported id {:?}", id_),
-            },
-        )
+        Ok(match message_id {
+            0 => Answer::AnswerNewObject(
+                AnswerNewObject::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            1 => Answer::AnswerReload(AnswerReload::deserialize(value).map_err(de::Error::custom)?),
+            2 => Answer::AnswerPrint(AnswerPrint::deserialize(value).map_err(de::Error::custom)?),
+            3 => Answer::AnswerError(AnswerError::deserialize(value).map_err(de::Error::custom)?),
+            4 => Answer::AnswerCustomMessage(
+                AnswerCustomMessage::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            5 => Answer::AnswerReturn(AnswerReturn::deserialize(value).map_err(de::Error::custom)?),
+            6 => Answer::AnswerGameSaved(
+                AnswerGameSaved::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            7 => Answer::AnswerObjectCreated(
+                AnswerObjectCreated::deserialize(value).map_err(de::Error::custom)?,
+            ),
+            id => return Err(de::Error::custom(format!("unsupported messageID {id}"))),
+        })
     }
 }
 
@@ -294,8 +495,8 @@ impl<'de> serde::Deserialize<'de> for Answer {
 #[derive(Deserialize, Debug)]
 pub struct AnswerNewObject {
     /// Contains the state of the object
-    #[serde(rename = "scriptStates")]
-    pub script_states: Value,
+    #[serde(rename = "scriptStates", default)]
+    pub script_states: Vec<ScriptState>,
 }
 
 impl TryFrom<Answer> for AnswerNewObject {
@@ -334,12 +535,13 @@ impl TryFrom<Answer> for AnswerNewObject {
 /// ```
 #[derive(Deserialize, Debug)]
 pub struct AnswerReload {
-    /// Path to the save file of the current save
-    #[serde(rename = "savePath")]
-    pub save_path: String,
+    /// Path to the save file of the current save.
+    /// This is `None` if the game hasn't been saved to disk yet.
+    #[serde(rename = "savePath", default)]
+    pub save_path: Option<String>,
     /// Contains a list objects and their state
-    #[serde(rename = "scriptStates")]
-    pub script_states: Value,
+    #[serde(rename = "scriptStates", default)]
+    pub script_states: Vec<ScriptState>,
 }
 
 impl TryFrom<Answer> for AnswerReload {
@@ -352,6 +554,25 @@ impl TryFrom<Answer> for AnswerReload {
     }
 }
 
+impl AnswerReload {
+    /// Returns the Lua script of Global, found by its guid "-1" regardless of its position in the list.
+    /// Returns `None` if the script states don't contain Global.
+    pub fn global_script(&self) -> Option<String> {
+        self.global().map(|state| state.script.clone())
+    }
+
+    /// Returns the UI XML of Global, found by its guid "-1" regardless of its position in the list.
+    /// Returns `None` if the script states don't contain Global or Global has no UI XML.
+    pub fn global_ui(&self) -> Option<String> {
+        self.global().and_then(|state| state.ui.clone())
+    }
+
+    /// Returns the script state of Global.
+    fn global(&self) -> Option<&ScriptState> {
+        self.script_states.iter().find(|state| state.guid == "-1")
+    }
+}
+
 /// TTS sends all `print()` messages in a [`AnswerPrint`] response.
 ///
 /// # Example
@@ -364,7 +585,7 @@ impl TryFrom<Answer> for AnswerReload {
 #[derive(Deserialize, Debug)]
 pub struct AnswerPrint {
     /// Message that got printed
-    #[serde(rename = "message")]
+    #[serde(rename = "message", default)]
     pub message: String,
 }
 
@@ -398,10 +619,23 @@ pub struct AnswerError {
     #[serde(rename = "guid")]
     pub guid: String,
     /// Description of the error
-    #[serde(rename = "errorMessagePrefix")]
+    #[serde(rename = "errorMessagePrefix", default)]
     pub error_message_prefix: String,
 }
 
+/// Formats the error like the game does in its console, followed by the guid of the object.
+impl fmt::Display for AnswerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} (guid: {})",
+            self.error_message_prefix, self.error, self.guid
+        )
+    }
+}
+
+impl std::error::Error for AnswerError {}
+
 impl TryFrom<Answer> for AnswerError {
     type Error = Error;
     fn try_from(answer: Answer) -> Result<Self, Self::Error> {
@@ -412,6 +646,61 @@ impl TryFrom<Answer> for AnswerError {
     }
 }
 
+impl AnswerError {
+    /// Returns the number of the chunk the error occurred in, e.g. `0` for `chunk_0:(36,4-8): ...`.
+    pub fn chunk(&self) -> Option<u32> {
+        let (chunk, _) = self.error.strip_prefix("chunk_")?.split_once(':')?;
+        chunk.parse().ok()
+    }
+
+    /// Returns the line the error occurred on, e.g. `36` for `chunk_0:(36,4-8): ...`.
+    pub fn line(&self) -> Option<u32> {
+        let (_, position) = self.error.split_once(":(")?;
+        let (line, _) = position.split_once(',')?;
+        line.parse().ok()
+    }
+
+    /// Looks up the source file of the chunk the error occurred in, as tracked by `sources` during the reload.
+    pub fn source_file<'a>(&self, sources: &'a ChunkSources) -> Option<&'a Path> {
+        sources.get(self.chunk()?)
+    }
+}
+
+/// Maps the chunks TTS numbers loaded scripts with, e.g. `chunk_7`, to the files the scripts were loaded from.
+///
+/// TTS loads the scripts of a reload in the order they were sent, numbering each non-empty script
+/// with the next chunk. Track the script states of a reload with [`ChunkSources::track`] to find out
+/// which file an [`AnswerError`] refers to with [`AnswerError::source_file`].
+#[derive(Debug, Clone, Default)]
+pub struct ChunkSources {
+    chunks: Vec<Option<PathBuf>>,
+}
+
+impl ChunkSources {
+    /// Creates an empty map of chunks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks the chunks of the scripts in `script_states`, in the order they get sent with the reload.
+    /// `files` maps the guids of objects to the files their scripts were loaded from.
+    /// Chunks of objects that aren't in `files` don't have a source file.
+    pub fn track(&mut self, script_states: &[ScriptState], files: &HashMap<String, PathBuf>) {
+        self.chunks.clear();
+        self.chunks.extend(
+            script_states
+                .iter()
+                .filter(|state| !state.script.is_empty())
+                .map(|state| files.get(&state.guid).cloned()),
+        );
+    }
+
+    /// Returns the file the script of the given chunk was loaded from.
+    pub fn get(&self, chunk: u32) -> Option<&Path> {
+        self.chunks.get(chunk as usize)?.as_deref()
+    }
+}
+
 /// Custom Messages are sent by calling `sendExternalMessage` with the table of data you wish to send.
 ///
 /// # Example
@@ -424,7 +713,7 @@ impl TryFrom<Answer> for AnswerError {
 #[derive(Deserialize, Debug)]
 pub struct AnswerCustomMessage {
     /// Content of the custom message
-    #[serde(rename = "customMessage")]
+    #[serde(rename = "customMessage", default)]
     pub custom_message: Value,
 }
 
@@ -449,17 +738,34 @@ impl TryFrom<Answer> for AnswerCustomMessage {
 /// }
 /// ```
 #[derive(Deserialize, Debug)]
+#[serde(from = "RawAnswerReturn")]
 pub struct AnswerReturn {
     /// Return Id of message that got executed
-    #[serde(rename = "returnID")]
     pub return_id: u64,
-    #[serde(
-        rename = "returnValue",
-        deserialize_with = "deserialize_json_string",
-        default
-    )]
-    /// The Value that got returned
+    /// The Value that got returned. Strings containing valid JSON get deserialized,
+    /// so a Lua function returning `"123"` results in the number `123`
     pub return_value: Value,
+    /// The Value that got returned, exactly as TTS sent it without deserializing JSON strings
+    pub raw_return_value: Value,
+}
+
+/// The [`AnswerReturn`] as it gets sent by TTS, before JSON strings in the return value get deserialized
+#[derive(Deserialize)]
+struct RawAnswerReturn {
+    #[serde(rename = "returnID")]
+    return_id: u64,
+    #[serde(rename = "returnValue", default)]
+    return_value: Value,
+}
+
+impl From<RawAnswerReturn> for AnswerReturn {
+    fn from(raw: RawAnswerReturn) -> Self {
+        Self {
+            return_id: raw.return_id,
+            return_value: parse_json_string(raw.return_value.clone()),
+            raw_return_value: raw.return_value,
+        }
+    }
 }
 
 impl TryFrom<Answer> for AnswerReturn {
@@ -474,16 +780,48 @@ impl TryFrom<Answer> for AnswerReturn {
 
 /// Returns the return value of the message as a [`Value`]. Valid JSON strings get deserialized if possible.
 /// If deserialization fails JSON strings get returned as a [`Value::String`] instead.
-fn deserialize_json_string<'de, D>(deserializer: D) -> Result<Value, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    match Option::deserialize(deserializer)? {
-        Some(val) => match val {
-            Value::String(val) => Ok(serde_json::from_str(&val).unwrap_or(Value::String(val))),
-            other => Ok(other),
-        },
-        None => Ok(Value::Null),
+fn parse_json_string(value: Value) -> Value {
+    match value {
+        Value::String(val) => serde_json::from_str(&val).unwrap_or(Value::String(val)),
+        other => other,
+    }
+}
+
+/// Checks that the script states of a reload are an array of objects that each have a guid.
+pub(crate) fn validate_script_states(script_states: &Value) -> Result<(), Error> {
+    let Value::Array(states) = script_states else {
+        return Err(Error::InvalidReloadPayload(format!(
+            "expected an array of script states, got {script_states}"
+        )));
+    };
+
+    for (index, state) in states.iter().enumerate() {
+        if !state.is_object() {
+            return Err(Error::InvalidReloadPayload(format!(
+                "script state {index} is not an object"
+            )));
+        }
+        if !state.get("guid").is_some_and(Value::is_string) {
+            return Err(Error::InvalidReloadPayload(format!(
+                "script state {index} has no guid"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Maximum number of characters shown of scripts and UI XML when debug printing
+const PREVIEW_LEN: usize = 100;
+
+/// Debug formats a string, truncated to [`PREVIEW_LEN`] characters followed by its full length.
+struct Preview<'a>(&'a str);
+
+impl fmt::Debug for Preview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.char_indices().nth(PREVIEW_LEN) {
+            Some((end, _)) => write!(f, "{:?}…({} bytes)", &self.0[..end], self.0.len()),
+            None => write!(f, "{:?}", self.0),
+        }
     }
 }
 
@@ -529,12 +867,36 @@ impl TryFrom<Answer> for AnswerObjectCreated {
 
 /////////////////////////////////////////////////////////////////////////////
 
+/// Interval in which [`ExternalEditorApi::wait_until_ready`] polls the game
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 impl ExternalEditorApi {
     /// Get a list containing the states for every object. Returns an [`AnswerReload`] message on success.
     /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
     pub fn get_scripts(&self) -> io::Result<AnswerReload> {
-        self.send(MessageGetScripts::new().as_message())?;
-        Ok(self.wait())
+        self.request(MessageGetScripts::new())
+    }
+
+    /// Get a list containing the Lua script and UI XML of every object as [`ScriptState`]s.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// Passing the states back to [`ExternalEditorApi::reload_states`] keeps the UI XML of every
+    /// object, including objects other than Global.
+    pub fn get_script_states(&self) -> io::Result<Vec<ScriptState>> {
+        Ok(self.get_scripts()?.script_states)
+    }
+
+    /// Get a list containing the Lua scripts for every object, without their UI XML.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// TTS always sends the UI XML along with the scripts, so this doesn't reduce the amount of
+    /// transferred data, but the UI XML gets dropped right away instead of being kept in memory.
+    pub fn get_scripts_lua_only(&self) -> io::Result<Vec<ScriptState>> {
+        let mut states = self.get_script_states()?;
+        for state in &mut states {
+            state.ui = None;
+        }
+        Ok(states)
     }
 
     /// Update the Lua scripts and UI XML for any objects listed in the message,
@@ -545,9 +907,82 @@ impl ExternalEditorApi {
     /// Any objects mentioned have both their Lua script and their UI XML updated.
     /// If no value is set for either the "script" or "ui" key then the
     /// corresponding Lua script or UI XML is deleted.
+    ///
+    /// `script_states` gets sent as is, including keys that [`ScriptState`] doesn't know about.
+    /// If it isn't an array of objects that each have a `guid`, an [`Error::InvalidReloadPayload`]
+    /// gets returned as an [`io::Error`] of kind [`io::ErrorKind::InvalidInput`] without sending anything.
     pub fn reload(&self, script_states: Value) -> io::Result<AnswerReload> {
-        self.send(MessageReload::new(script_states).as_message())?;
-        Ok(self.wait())
+        validate_script_states(&script_states)?;
+        self.request(MessageReload::new_raw(script_states))
+    }
+
+    /// Update the Lua scripts and UI XML for the objects in `states` and reloads the save file.
+    /// This is the typed equivalent of [`ExternalEditorApi::reload`].
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// Objects with `ui` set to `None` get their UI XML deleted.
+    pub fn reload_states(&self, states: Vec<ScriptState>) -> io::Result<AnswerReload> {
+        self.request(MessageReload::new(states))
+    }
+
+    /// Update the Lua scripts and UI XML for any objects listed in the message and reloads the save file,
+    /// like [`ExternalEditorApi::reload`], and collects the [`AnswerError`] messages of scripts that fail
+    /// while loading. Errors are collected until the [`AnswerReload`] arrives and for `window` afterwards.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If `script_states` isn't an array of objects that each have a `guid`, an [`Error::InvalidReloadPayload`]
+    /// gets returned as an [`io::Error`] of kind [`io::ErrorKind::InvalidInput`] without sending anything.
+    pub fn reload_with_errors(
+        &self,
+        script_states: Value,
+        window: Duration,
+    ) -> io::Result<(AnswerReload, Vec<AnswerError>)> {
+        validate_script_states(&script_states)?;
+        let _guard = self.lock_requests();
+        self.send(MessageReload::new_raw(script_states).as_message())?;
+
+        let mut errors = Vec::new();
+        let reload = loop {
+            match self.read()? {
+                Answer::AnswerReload(answer) => break answer,
+                Answer::AnswerError(answer) => errors.push(answer),
+                _ => continue,
+            }
+        };
+
+        let deadline = Instant::now() + window;
+        loop {
+            match self.read_before(Some(deadline)) {
+                Ok(Answer::AnswerError(answer)) => errors.push(answer),
+                Ok(_) => continue,
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((reload, errors))
+    }
+
+    /// Update the Lua scripts and UI XML for any objects listed in the message and reloads the save file,
+    /// like [`ExternalEditorApi::reload`], but waits for the [`AnswerReload`] only if `mode` is [`ReloadMode::WaitFull`].
+    /// Returns `None` for [`ReloadMode::FireAndForget`].
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If `script_states` isn't an array of objects that each have a `guid`, an [`Error::InvalidReloadPayload`]
+    /// gets returned as an [`io::Error`] of kind [`io::ErrorKind::InvalidInput`] without sending anything.
+    pub fn reload_with_mode(
+        &self,
+        script_states: Value,
+        mode: ReloadMode,
+    ) -> io::Result<Option<AnswerReload>> {
+        match mode {
+            ReloadMode::WaitFull => self.reload(script_states).map(Some),
+            ReloadMode::FireAndForget => {
+                validate_script_states(&script_states)?;
+                let _guard = self.lock_requests();
+                self.send_reload(MessageReload::new_raw(script_states).as_message())?;
+                Ok(None)
+            }
+        }
     }
 
     /// Send a custom message to be forwarded to the `onExternalMessage` event handler
@@ -561,11 +996,53 @@ impl ExternalEditorApi {
         Ok(())
     }
 
+    /// Reads incoming answers and calls `f` with the payload of every [`AnswerCustomMessage`]
+    /// deserialized into `T`. Other answers and payloads that can't be deserialized into `T` get skipped.
+    /// This function will block the calling thread until reading an answer fails.
+    ///
+    /// Use a [`ShutdownHandle`](crate::tcp::ShutdownHandle) to stop listening from another thread,
+    /// in which case an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    pub fn on_custom_message<T, F>(&self, mut f: F) -> io::Result<()>
+    where
+        T: DeserializeOwned,
+        F: FnMut(T),
+    {
+        loop {
+            if let Answer::AnswerCustomMessage(answer) = self.read()? {
+                if let Ok(payload) = T::deserialize(answer.custom_message) {
+                    f(payload);
+                }
+            }
+        }
+    }
+
+    /// Reads incoming answers until the payload of an [`AnswerCustomMessage`] satisfies `pred`,
+    /// and returns that payload. Other answers get skipped.
+    /// This function will block the calling thread until a matching custom message gets received.
+    ///
+    /// If the api gets shut down with a [`ShutdownHandle`](crate::tcp::ShutdownHandle),
+    /// an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    pub fn wait_for_custom<F: Fn(&Value) -> bool>(&self, pred: F) -> io::Result<Value> {
+        loop {
+            if let Answer::AnswerCustomMessage(answer) = self.read()? {
+                if pred(&answer.custom_message) {
+                    return Ok(answer.custom_message);
+                }
+            }
+        }
+    }
+
     /// Executes a lua script globally and returns the value in a [`AnswerReturn`] message.
     /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
     pub fn execute(&self, script: String) -> io::Result<AnswerReturn> {
-        self.send(MessageExecute::new(script).as_message())?;
-        Ok(self.wait())
+        let message = MessageExecute {
+            return_id: self.next_return_id(),
+            ..MessageExecute::new(script)
+        };
+        self.execute_message(message, None)
     }
 
     /// Executes a lua script on an object and returns the value in a [`AnswerReturn`] message.
@@ -576,8 +1053,188 @@ impl ExternalEditorApi {
     /// Object reference not set to an instance of an object".
     /// Once the in-game editor shows a script associated with an object
     /// then TTS will be able to execute Lua code sent via JSON message for that object.
+    /// Use [`ExternalEditorApi::execute_on_object_ensuring`] to attach a placeholder script if necessary.
+    ///
+    /// If TTS sends an [`AnswerError`] for the object while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
     pub fn execute_on_object(&self, script: String, guid: String) -> io::Result<AnswerReturn> {
-        self.send(MessageExecute::new_object(script, guid).as_message())?;
-        Ok(self.wait())
+        let message = MessageExecute {
+            return_id: self.next_return_id(),
+            ..MessageExecute::new_object(script, guid)
+        };
+        self.execute_message(message, None)
+    }
+
+    /// Executes a lua script globally with the given returnID, and returns the value in the
+    /// [`AnswerReturn`] message that carries the same returnID. Returns with other returnIDs get skipped.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// The returnID must be unique across all requests that might still be answered, otherwise a late
+    /// return of another request can be mistaken for this one. The other execute functions use returnIDs
+    /// counting up from 1, so ids from a separate range should be used alongside them.
+    ///
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
+    pub fn execute_with_return_id(
+        &self,
+        script: String,
+        return_id: u64,
+    ) -> io::Result<AnswerReturn> {
+        let message = MessageExecute {
+            return_id,
+            ..MessageExecute::new(script)
+        };
+        self.execute_message(message, None)
+    }
+
+    /// Executes a lua script globally and returns the value exactly as TTS sent it.
+    /// Unlike [`AnswerReturn::return_value`], strings containing valid JSON stay strings.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
+    pub fn execute_raw_value(&self, script: String) -> io::Result<Value> {
+        Ok(self.execute(script)?.raw_return_value)
+    }
+
+    /// Executes a lua script globally and deserializes its return value into `T`.
+    /// Return `JSON.encode(...)` from the script to deserialize tables into structs.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If the return value doesn't match `T`, an [`io::Error`] of kind [`io::ErrorKind::InvalidData`] gets returned.
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
+    pub fn execute_typed<T: DeserializeOwned>(&self, script: String) -> io::Result<T> {
+        Ok(serde_json::from_value(self.execute(script)?.return_value)?)
+    }
+
+    /// Reads a lua script from a file and executes it globally. Returns the value in a [`AnswerReturn`] message.
+    /// If the file can't be read or no connection to the game can be established,
+    /// an [`io::Error`] gets returned instead.
+    ///
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
+    pub fn execute_file(&self, path: &Path) -> io::Result<AnswerReturn> {
+        self.execute(fs::read_to_string(path)?)
+    }
+
+    /// Executes a lua script globally and returns its return value together with the messages
+    /// printed to the game's console until the return value arrived.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// If TTS sends an [`AnswerError`] for the global script while waiting for the return value,
+    /// an [`Error::LuaError`] gets returned as an [`io::Error`] instead.
+    pub fn execute_with_prints(&self, script: String) -> io::Result<(Value, Vec<String>)> {
+        let message = MessageExecute {
+            return_id: self.next_return_id(),
+            ..MessageExecute::new(script)
+        };
+        let mut prints = Vec::new();
+        let answer = self.execute_message_with(message, None, |answer| {
+            if let Answer::AnswerPrint(answer) = answer {
+                prints.push(answer.message);
+            }
+        })?;
+        Ok((answer.return_value, prints))
+    }
+
+    /// Sends a [`MessageExecute`] and waits for the [`AnswerReturn`] with its returnID, or for an [`AnswerError`]
+    /// of the object the script gets executed on. Gives up once the `deadline` has passed.
+    pub(crate) fn execute_message(
+        &self,
+        message: MessageExecute,
+        deadline: Option<Instant>,
+    ) -> io::Result<AnswerReturn> {
+        self.execute_message_with(message, deadline, |_| {})
+    }
+
+    /// Like [`ExternalEditorApi::execute_message`], but passes every other answer that arrives
+    /// while waiting to `on_answer`.
+    fn execute_message_with(
+        &self,
+        message: MessageExecute,
+        deadline: Option<Instant>,
+        mut on_answer: impl FnMut(Answer),
+    ) -> io::Result<AnswerReturn> {
+        let guid = message.guid.clone();
+        let return_id = message.return_id;
+        let _guard = self.lock_requests();
+        self.send(message.as_message())?;
+        loop {
+            let answer = self.read_before(deadline).inspect_err(|_| {
+                // The return might still arrive after giving up
+                self.abandon(return_id);
+            })?;
+            match answer {
+                Answer::AnswerReturn(answer) if answer.return_id == return_id => return Ok(answer),
+                Answer::AnswerError(answer) if answer.guid == guid => {
+                    return Err(Error::LuaError(answer).into())
+                }
+                answer => on_answer(answer),
+            }
+        }
+    }
+
+    /// Compiles a Lua script with the Lua compiler of the game without running it.
+    /// Returns the compile error if the script has a syntax error, or `None` if it compiles.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    pub fn check_syntax(&self, script: &str) -> io::Result<Option<String>> {
+        let script = format!(
+            "local _, err = load({})\nreturn err",
+            crate::objects::lua_string(script)
+        );
+
+        // The raw value keeps errors that are valid JSON themselves as strings
+        match self.execute_raw_value(script)? {
+            Value::String(err) => Ok(Some(err)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Blocks until the loaded game is ready to execute Lua code, or until the `timeout` has elapsed.
+    ///
+    /// Right after launching Tabletop Simulator the game may accept connections without being able
+    /// to run scripts yet, so messages sent during that time are silently dropped. This function polls
+    /// the game by executing `return true` until a matching [`AnswerReturn`] arrives.
+    /// If the game isn't ready in time, an [`io::Error`] of kind [`io::ErrorKind::TimedOut`] gets returned.
+    pub fn wait_until_ready(&self, timeout: Duration) -> io::Result<()> {
+        let _guard = self.lock_requests();
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            let retry = (Instant::now() + READY_POLL_INTERVAL).min(deadline);
+
+            // The connection gets refused until the game has been loaded
+            let return_id = self.next_return_id();
+            let message = MessageExecute {
+                return_id,
+                ..MessageExecute::new(String::from("return true"))
+            };
+            if self.send(message.as_message()).is_err() {
+                thread::sleep(retry.saturating_duration_since(Instant::now()));
+                continue;
+            }
+
+            loop {
+                match self.read_before(Some(retry)) {
+                    Ok(Answer::AnswerReturn(answer))
+                        if answer.return_id == return_id
+                            && answer.return_value == Value::Bool(true) =>
+                    {
+                        return Ok(());
+                    }
+                    Ok(_) => continue,
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                        self.abandon(return_id);
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "game did not become ready in time",
+        ))
     }
 }
@@ -0,0 +1,8 @@
+//! Re-exports of the commonly used types
+//!
+//! ```
+//! use tts_external_api::prelude::*;
+//! ```
+
+pub use crate::messages::*;
+pub use crate::{json, Error, ExternalEditorApi, ExternalEditorApiBuilder, TtsApi, Value};
@@ -0,0 +1,94 @@
+//! A single entry point for executing Lua code with timeouts and retries
+
+use crate::error::Error;
+use crate::messages::MessageExecute;
+use crate::{tcp::ExternalEditorApi, Value};
+use std::io;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Options for [`ExternalEditorApi::run`]
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Maximum time to wait for the return value of a single attempt. Waits forever if `None`.
+    pub timeout: Option<Duration>,
+    /// Number of times the script gets sent again if the game can't be reached or doesn't answer in time
+    pub retries: u32,
+}
+
+impl RunOptions {
+    /// Constructs new options that wait forever and don't retry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum time to wait for the return value of a single attempt
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the number of times the script gets sent again
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+/// Errors returned by [`ExternalEditorApi::run`]
+#[derive(Error, Debug)]
+pub enum RunError {
+    /// The game didn't answer in time
+    #[error("timed out waiting for the return value")]
+    Timeout,
+    /// The script caused a Lua error
+    #[error("{0}")]
+    LuaError(String),
+    /// No connection to the game could be established
+    #[error("can't connect to the game, is a save loaded?")]
+    NotConnected,
+    /// Any other IO error
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl From<io::Error> for RunError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => RunError::Timeout,
+            io::ErrorKind::ConnectionRefused => RunError::NotConnected,
+            _ => match err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+                Some(Error::LuaError(answer)) => RunError::LuaError(answer.to_string()),
+                _ => RunError::Io(err),
+            },
+        }
+    }
+}
+
+impl ExternalEditorApi {
+    /// Executes a lua script globally and returns its return value.
+    ///
+    /// Unlike [`ExternalEditorApi::execute`], the script gets sent again if the game can't be reached or
+    /// doesn't answer within the timeout, as configured in the [`RunOptions`], and the possible failures
+    /// are distinguished by the [`RunError`]. Lua errors aren't retried.
+    pub fn run(&self, script: String, options: RunOptions) -> Result<Value, RunError> {
+        let mut attempt = 0;
+        loop {
+            let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+            let message = MessageExecute {
+                return_id: self.next_return_id(),
+                ..MessageExecute::new(script.clone())
+            };
+            match self
+                .execute_message(message, deadline)
+                .map_err(RunError::from)
+            {
+                Ok(answer) => return Ok(answer.return_value),
+                Err(RunError::Timeout | RunError::NotConnected) if attempt < options.retries => {
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
@@ -1,56 +1,720 @@
 //! The TCP connection used for communication between the external API and Tabletop Simulator
 
-use crate::messages::{Answer, Message};
+use crate::{
+    error::Error,
+    messages::{Answer, Message},
+};
+use crate::{json, Value};
+use std::collections::BTreeSet;
 use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of abandoned returnIDs that are remembered, see [`ExternalEditorApi::abandon`]
+pub const MAX_ABANDONED_RETURNS: usize = 1024;
+
+/// Interval in which the listener gets polled while waiting for a connection with a deadline
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Default IP address used for both listening and sending, the IPv4 loopback address `127.0.0.1`
+pub const DEFAULT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Time [`ExternalEditorApi::drain`] waits for a pending connection to finish sending its message
+const DRAIN_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default port the [`ExternalEditorApi`] listens on for answers from Tabletop Simulator
+pub const DEFAULT_LISTEN_PORT: u16 = 39998;
+
+/// Default port Tabletop Simulator listens on for messages from the [`ExternalEditorApi`]
+pub const DEFAULT_SEND_PORT: u16 = 39999;
+
+/// Default maximum size of an incoming message in bytes
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default time writing a message to Tabletop Simulator may take before giving up
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A struct representing Tabletop Simulators [External Editor API](https://api.tabletopsimulator.com/externaleditorapi/).
+///
+/// # Threads
+///
+/// The struct can be shared between threads, for example by wrapping it in an [`Arc`].
+/// Functions that send a message and wait for its answer, like [`ExternalEditorApi::execute`],
+/// hold an internal lock until the answer has arrived. Concurrent requests from multiple threads
+/// are therefore processed one after another, and each request receives its own answer.
+///
+/// [`ExternalEditorApi::read`] and [`ExternalEditorApi::wait`] don't take that lock. Reading answers
+/// on one thread while another thread waits for the answer of a request can take that answer away from it.
 #[derive(Debug)]
 pub struct ExternalEditorApi {
     /// TcpListener used for listening to incoming messages
     pub listener: TcpListener,
+    send_addr: SocketAddr,
+    max_message_size: usize,
+    write_timeout: Option<Duration>,
+    serialize_during_reload: bool,
+    reload_pending: AtomicBool,
+    #[cfg(feature = "arbitrary_precision")]
+    number_mode: NumberMode,
+    trace: Option<Mutex<File>>,
+    read_timeout: Mutex<Option<Duration>>,
+    request_lock: Mutex<()>,
+    next_return_id: AtomicU64,
+    abandoned_returns: Mutex<BTreeSet<u64>>,
+    polling_accepts: Mutex<usize>,
+    shutdown: Arc<AtomicBool>,
 }
 
 impl ExternalEditorApi {
     /// Creates a new ExternalEditorApi struct and binds the TcpListener to its socket address.
+    ///
+    /// Use [`ExternalEditorApi::builder`] to change the default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listen port is already in use. Use [`ExternalEditorApi::try_new`] to handle the error instead.
     pub fn new() -> Self {
-        let listener = TcpListener::bind("127.0.0.1:39998").unwrap();
-        Self { listener }
+        Self::try_new().unwrap()
+    }
+
+    /// Creates a new ExternalEditorApi struct with the default settings, like [`ExternalEditorApi::new`].
+    ///
+    /// If the listen port is already in use, for example by another running instance,
+    /// an [`io::Error`] gets returned instead of panicking.
+    pub fn try_new() -> io::Result<Self> {
+        Self::with_ports(DEFAULT_LISTEN_PORT, DEFAULT_SEND_PORT)
+    }
+
+    /// Creates a new ExternalEditorApi struct that listens on `listen_port` and sends messages to `send_port`.
+    ///
+    /// If the listen port is already in use, an [`io::Error`] gets returned instead of panicking.
+    /// If both ports are the same, an [`Error::SamePort`] gets returned as an [`io::Error`]
+    /// of kind [`io::ErrorKind::InvalidInput`].
+    pub fn with_ports(listen_port: u16, send_port: u16) -> io::Result<Self> {
+        Self::builder()
+            .listen_port(listen_port)
+            .send_port(send_port)
+            .build()
+    }
+
+    /// Returns an [`ExternalEditorApiBuilder`] with the default settings, used to configure
+    /// the ports, timeouts and message size limit of the api before creating it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use tts_external_api::ExternalEditorApi;
+    ///
+    /// let api = ExternalEditorApi::builder()
+    ///     .listen_port(49998)
+    ///     .send_port(49999)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder() -> ExternalEditorApiBuilder {
+        ExternalEditorApiBuilder::new()
     }
 
     /// Sends a [`Message`] in a TcpStream. If no connection to the game can be established, an [`io::Error`] gets returned.
+    ///
+    /// If writing the message takes longer than the write timeout of the api, an [`Error::SendTimeout`]
+    /// gets returned as an [`io::Error`] of kind [`io::ErrorKind::TimedOut`].
+    ///
+    /// Every message gets sent in a new connection that is closed afterwards, since Tabletop Simulator
+    /// reads a message until its connection gets closed.
+    ///
+    /// If [`ExternalEditorApiBuilder::serialize_during_reload`] is enabled and a reload is in progress,
+    /// this blocks until the [`AnswerReload`](crate::messages::AnswerReload) of the reload has been received.
     pub fn send(&self, message: Message) -> io::Result<()> {
-        let mut stream = TcpStream::connect("127.0.0.1:39999")?;
-        let json_message = serde_json::to_string(&message).unwrap();
-        stream.write_all(json_message.as_bytes()).unwrap();
-        stream.flush().unwrap();
+        self.wait_for_pending_reload()?;
+        self.send_now(message)
+    }
+
+    /// Sends a reload without waiting for its [`AnswerReload`](crate::messages::AnswerReload),
+    /// and marks the reload as in progress until the answer gets read.
+    ///
+    /// The reload gets marked before sending, so a concurrent read can't receive the answer before
+    /// the reload is marked. If sending fails, the mark gets removed again.
+    pub(crate) fn send_reload(&self, message: Message) -> io::Result<()> {
+        self.wait_for_pending_reload()?;
+        self.reload_pending.store(true, Ordering::SeqCst);
+        self.send_now(message).inspect_err(|_| {
+            self.reload_pending.store(false, Ordering::SeqCst);
+        })
+    }
+
+    /// Blocks until a reload in progress has finished, if [`ExternalEditorApiBuilder::serialize_during_reload`] is enabled.
+    fn wait_for_pending_reload(&self) -> io::Result<()> {
+        if self.serialize_during_reload {
+            // Reading the AnswerReload clears the flag
+            while self.reload_pending.load(Ordering::SeqCst) {
+                self.read_before(None)?;
+            }
+        }
         Ok(())
     }
 
+    /// Sends a [`Message`] right away, regardless of a reload in progress.
+    fn send_now(&self, message: Message) -> io::Result<()> {
+        if self.trace.is_some() {
+            self.trace("sent", &serde_json::to_string(&message)?);
+        }
+
+        let stream = TcpStream::connect(self.send_addr)?;
+        stream.set_write_timeout(self.write_timeout)?;
+
+        write_to(&stream, &message).map_err(|err| match (err.kind(), self.write_timeout) {
+            // Platforms report an expired write timeout with either kind
+            (io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut, Some(timeout)) => {
+                Error::SendTimeout(timeout).into()
+            }
+            _ => err,
+        })?;
+
+        // Shutting down the write half signals TTS that the message is complete
+        match stream.shutdown(Shutdown::Write) {
+            // The game might have closed the connection already after reading the message
+            Err(err) if err.kind() != io::ErrorKind::NotConnected => Err(err),
+            _ => Ok(()),
+        }
+    }
+
     /// Accepts the next incoming [`Answer`] from the listener and deserializes it.
     /// This function will block the calling thread until a new TCP connection is established and an answer gets received.
-    pub fn read(&self) -> Answer {
-        serde_json::from_str(&self.read_string()).unwrap()
+    ///
+    /// If the answer isn't valid JSON or doesn't match any answer type, an [`Error::Json`] gets returned,
+    /// and the next answer can be read normally. Failing connections return an [`Error::Io`].
+    /// If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned.
+    /// If the listener can't accept connections anymore, an [`Error::ListenerClosed`] gets returned instead.
+    pub fn read(&self) -> Result<Answer, Error> {
+        Ok(self.with_read_timeout(|deadline| self.read_before(deadline))?)
     }
 
     /// Accepts the next incoming [`Answer`] from the listener as a String.
     /// This function will block the calling thread until a new TCP connection is established and an answer gets received.
-    pub fn read_string(&self) -> String {
-        let (mut stream, _addr) = self.listener.accept().unwrap();
-        let mut buffer = String::new();
-        stream.read_to_string(&mut buffer).unwrap();
-        buffer
+    ///
+    /// If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    /// If the listener can't accept connections anymore, an [`Error::ListenerClosed`] gets returned instead.
+    /// If the answer isn't valid UTF-8, an [`Error::InvalidUtf8`] gets returned as an [`io::Error`]
+    /// of kind [`io::ErrorKind::InvalidData`], and the next answer can be read normally.
+    pub fn read_string(&self) -> io::Result<String> {
+        self.with_read_timeout(|deadline| self.read_string_before(deadline))
+    }
+
+    /// Sets how long [`ExternalEditorApi::read`] and [`ExternalEditorApi::read_string`] wait for an answer,
+    /// and with that every function that reads answers, like [`ExternalEditorApi::wait`].
+    /// The timeout applies to each answer separately. If no answer arrives in time, an [`Error::Timeout`]
+    /// gets returned as an [`io::Error`] of kind [`io::ErrorKind::TimedOut`].
+    /// A timeout of `None` waits indefinitely, which is the default.
+    ///
+    /// If a timeout of zero is passed, an [`io::Error`] of kind [`io::ErrorKind::InvalidInput`] gets returned.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the read timeout must not be zero",
+            ));
+        }
+        *self
+            .read_timeout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = timeout;
+        Ok(())
+    }
+
+    /// Returns the read timeout set with [`ExternalEditorApi::set_read_timeout`].
+    pub fn read_timeout(&self) -> Option<Duration> {
+        *self
+            .read_timeout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Reads incoming [`Answer`] messages until an answer matches the generic.
     /// This function will block the calling thread until a new TCP connection is established and an answer gets received.
-    pub fn wait<T: TryFrom<Answer>>(&self) -> T {
+    ///
+    /// If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    pub fn wait<T: TryFrom<Answer>>(&self) -> io::Result<T> {
+        loop {
+            if let Ok(answer) = T::try_from(self.read()?) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Reads incoming [`Answer`] messages until an answer matches the generic, and calls `on_skip`
+    /// with every answer that gets skipped because it doesn't match.
+    /// This is useful for finding out why a wait doesn't return, e.g. because of a flood of prints.
+    /// This function will block the calling thread until a new TCP connection is established and an answer gets received.
+    ///
+    /// If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    pub fn wait_with<T, F>(&self, mut on_skip: F) -> io::Result<T>
+    where
+        T: TryFrom<Answer, Error = Error>,
+        F: FnMut(&Answer),
+    {
+        loop {
+            match T::try_from(self.read()?) {
+                Ok(answer) => return Ok(answer),
+                Err(Error::AnswerError(answer)) => on_skip(&answer),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Reads incoming [`Answer`] messages until an answer matches the generic or the `deadline` has passed.
+    /// The deadline applies to the whole wait, no matter how many other answers arrive in the meantime.
+    ///
+    /// If no matching answer arrives before the deadline, an [`io::Error`] of kind [`io::ErrorKind::TimedOut`]
+    /// gets returned. If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned instead.
+    pub fn wait_deadline<T: TryFrom<Answer>>(&self, deadline: Instant) -> io::Result<T> {
+        loop {
+            if let Ok(answer) = T::try_from(self.read_before(Some(deadline))?) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Accepts and returns all answers that are currently waiting to be read, without blocking
+    /// for new ones. Answers that can't be read or deserialized get dropped.
+    ///
+    /// This is useful for discarding outdated answers, e.g. after a pause, before sending a request
+    /// and waiting for its answer.
+    pub fn drain(&self) -> Vec<Answer> {
+        let mut answers = Vec::new();
+        while let Ok(Some(stream)) = self.accept_pending() {
+            // The connection might not have finished sending its message yet
+            let deadline = Instant::now() + DRAIN_READ_TIMEOUT;
+            let answer = self
+                .read_stream(stream, Some(deadline))
+                .and_then(|buffer| self.parse_answer(&buffer));
+            if let Ok(answer) = answer {
+                if !self.take_abandoned(&answer) {
+                    answers.push(answer);
+                }
+            }
+        }
+        answers
+    }
+
+    /// Stops waiting for the [`AnswerReturn`](crate::messages::AnswerReturn) with the given returnID.
+    /// When that return arrives later, it gets discarded by all reads instead of being returned,
+    /// so it can't be mistaken for the answer of another request. Answers with other returnIDs aren't affected.
+    ///
+    /// Each abandoned returnID discards only the first return that carries it, and stays registered
+    /// until then. Returns that never arrive would stay registered forever, so only the
+    /// [`MAX_ABANDONED_RETURNS`] highest returnIDs are remembered and older ones get forgotten.
+    /// Executes that give up waiting, e.g. because of a deadline, abandon their returnID
+    /// automatically. Use this for executes sent manually, e.g. with [`ExternalEditorApi::send`].
+    pub fn abandon(&self, return_id: u64) {
+        let mut abandoned = self
+            .abandoned_returns
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        abandoned.insert(return_id);
+        if abandoned.len() > MAX_ABANDONED_RETURNS {
+            abandoned.pop_first();
+        }
+    }
+
+    /// Returns whether the answer is the return of an abandoned execute, and forgets the returnID if so.
+    fn take_abandoned(&self, answer: &Answer) -> bool {
+        match answer {
+            Answer::AnswerReturn(answer) => self
+                .abandoned_returns
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&answer.return_id),
+            _ => false,
+        }
+    }
+
+    /// Reads the next [`Answer`] if a connection is already waiting, without blocking for new ones.
+    /// Returns `Ok(None)` if there is nothing to read, which makes this usable in event loops.
+    ///
+    /// A waiting connection gets read until TTS has sent the complete message, so incomplete messages
+    /// don't get returned as malformed answers. If the message doesn't complete within a second,
+    /// an [`io::Error`] of kind [`io::ErrorKind::TimedOut`] gets returned instead.
+    /// If the api gets shut down with a [`ShutdownHandle`], an [`Error::Shutdown`] gets returned as an [`io::Error`].
+    pub fn try_read(&self) -> io::Result<Option<Answer>> {
+        let Some(stream) = self.accept_pending()? else {
+            return Ok(None);
+        };
+        let deadline = Instant::now() + DRAIN_READ_TIMEOUT;
+        let buffer = self.read_stream(stream, Some(deadline))?;
+        let answer = self.parse_answer(&buffer)?;
+        Ok((!self.take_abandoned(&answer)).then_some(answer))
+    }
+
+    /// Returns an iterator over the incoming answers, reading them with [`ExternalEditorApi::read`].
+    ///
+    /// Answers that can't be read or deserialized get yielded as errors, so the iteration can continue
+    /// past them. The iterator ends once the api gets shut down with a [`ShutdownHandle`],
+    /// or once the listener can't accept connections anymore.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use tts_external_api::{messages::Answer, ExternalEditorApi};
+    ///
+    /// let api = ExternalEditorApi::new();
+    /// for answer in api.incoming() {
+    ///     match answer {
+    ///         Ok(Answer::AnswerPrint(answer)) => println!("{}", answer.message),
+    ///         Ok(_) => {}
+    ///         Err(err) => eprintln!("{err}"),
+    ///     }
+    /// }
+    /// ```
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { api: self }
+    }
+
+    /// Returns a [`ShutdownHandle`] that can interrupt blocking reads of this api from another thread.
+    pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+        let mut addr = self.listen_addr()?;
+        // Connecting to an unspecified address fails on some platforms, e.g. Windows
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+        Ok(ShutdownHandle {
+            shutdown: Arc::clone(&self.shutdown),
+            addr,
+        })
+    }
+
+    /// Returns the socket address the listener is bound to.
+    /// This is useful for finding out which port got assigned when listening on port `0`.
+    pub fn listen_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Sends a message and waits for the answer of type `A`. Answers of other types get skipped.
+    /// If no connection to the game can be established, an [`io::Error`] gets returned instead.
+    ///
+    /// Functions like [`ExternalEditorApi::get_scripts`] are built on this. While waiting, an internal lock
+    /// is held so concurrent requests from other threads don't receive this answer.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use tts_external_api::messages::{AnswerReload, MessageGetScripts};
+    /// use tts_external_api::ExternalEditorApi;
+    ///
+    /// let api = ExternalEditorApi::new();
+    /// let answer: AnswerReload = api.request(MessageGetScripts::new()).unwrap();
+    /// ```
+    pub fn request<M, A>(&self, message: M) -> io::Result<A>
+    where
+        M: Into<Message>,
+        A: TryFrom<Answer>,
+    {
+        let _guard = self.lock_requests();
+        self.send(message.into())?;
+        self.wait()
+    }
+
+    /// Acquires the lock that makes sure only one request waits for its answer at a time.
+    pub(crate) fn lock_requests(&self) -> MutexGuard<'_, ()> {
+        // The lock doesn't protect any data, so a panic while holding it can be ignored
+        self.request_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Accepts the next incoming [`Answer`] and deserializes it, giving up once the `deadline` has passed.
+    /// If no deadline is given, this blocks the same way [`ExternalEditorApi::read`] does.
+    pub(crate) fn read_before(&self, deadline: Option<Instant>) -> io::Result<Answer> {
         loop {
-            if let Ok(answer) = T::try_from(self.read()) {
-                return answer;
+            let buffer = self.read_string_before(deadline)?;
+            let answer = self.parse_answer(&buffer)?;
+            if !self.take_abandoned(&answer) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Deserializes an incoming [`Answer`] and keeps track of pending reloads.
+    fn parse_answer(&self, buffer: &str) -> io::Result<Answer> {
+        let answer: Answer = serde_json::from_str(buffer).map_err(Error::Json)?;
+        #[cfg(feature = "arbitrary_precision")]
+        let answer = {
+            let mut answer = answer;
+            if self.number_mode == NumberMode::Lossy {
+                lossy_numbers(&mut answer);
             }
+            answer
+        };
+        if let Answer::AnswerReload(_) = answer {
+            self.reload_pending.store(false, Ordering::SeqCst);
+        }
+        Ok(answer)
+    }
+
+    /// Returns a returnID that hasn't been used by this api yet, so the [`AnswerReturn`](crate::messages::AnswerReturn)
+    /// of an execute can be told apart from the returns of other executes.
+    pub(crate) fn next_return_id(&self) -> u64 {
+        self.next_return_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Calls `read` with the deadline of the read timeout, and turns running out of time into an [`Error::Timeout`].
+    fn with_read_timeout<T, F>(&self, read: F) -> io::Result<T>
+    where
+        F: FnOnce(Option<Instant>) -> io::Result<T>,
+    {
+        let Some(timeout) = self.read_timeout() else {
+            return read(None);
+        };
+        read(Some(Instant::now() + timeout)).map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout(timeout).into(),
+            _ => err,
+        })
+    }
+
+    /// Accepts the next incoming [`Answer`] as a String, giving up once the `deadline` has passed.
+    fn read_string_before(&self, deadline: Option<Instant>) -> io::Result<String> {
+        let stream = self.accept_before(deadline)?;
+        self.read_stream(stream, deadline)
+    }
+
+    /// Reads an accepted connection until EOF as a String, giving up once the `deadline` has passed.
+    fn read_stream(&self, stream: TcpStream, deadline: Option<Instant>) -> io::Result<String> {
+        if let Some(deadline) = deadline {
+            stream.set_read_timeout(Some(remaining(deadline)?))?;
+        }
+
+        // Read one byte past the limit to detect messages that exceed it
+        let limit = self.max_message_size.saturating_add(1) as u64;
+        let mut buffer = Vec::new();
+        stream
+            .take(limit)
+            .read_to_end(&mut buffer)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock => io::Error::from(io::ErrorKind::TimedOut),
+                _ => err,
+            })?;
+        if buffer.len() > self.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "message exceeds the maximum size of {} bytes",
+                    self.max_message_size
+                ),
+            ));
         }
+
+        let buffer = String::from_utf8(buffer).map_err(|err| {
+            self.trace("received", &String::from_utf8_lossy(err.as_bytes()));
+            Error::InvalidUtf8(err)
+        })?;
+        self.trace("received", &buffer);
+        Ok(buffer)
+    }
+
+    /// Appends a raw message to the trace file as a JSON line, if tracing is enabled.
+    fn trace(&self, direction: &str, raw: &str) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+
+        let message = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs_f64())
+            .unwrap_or_default();
+        let line = json!({"time": time, "direction": direction, "message": message});
+
+        let mut file = trace.lock().unwrap_or_else(PoisonError::into_inner);
+        // Tracing is best effort and must not interfere with the communication
+        let _ = writeln!(file, "{line}");
+    }
+
+    /// Accepts the next incoming TCP connection. Without a deadline this blocks until a connection is established,
+    /// otherwise the listener gets polled until the deadline has passed.
+    fn accept_before(&self, deadline: Option<Instant>) -> io::Result<TcpStream> {
+        self.check_shutdown()?;
+        let Some(deadline) = deadline else {
+            let (stream, _addr) = loop {
+                match self.listener.accept() {
+                    Ok(connection) => break connection,
+                    // Another thread is polling the listener with a deadline
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        self.check_shutdown()?;
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(accept_error(err)),
+                }
+            };
+            // Accepted streams inherit the nonblocking flag on some platforms
+            stream.set_nonblocking(false)?;
+            // The connection might have been made by the ShutdownHandle to wake up the listener
+            self.check_shutdown()?;
+            return Ok(stream);
+        };
+
+        let result = self.polling(|| loop {
+            match self.listener.accept() {
+                Ok((stream, _addr)) => break Ok(stream),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if let Err(err) = self.check_shutdown() {
+                        break Err(err);
+                    }
+                    if Instant::now() >= deadline {
+                        break Err(io::Error::from(io::ErrorKind::TimedOut));
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => break Err(accept_error(err)),
+            }
+        })?;
+
+        // Accepted streams inherit the nonblocking flag on some platforms
+        let stream = result?;
+        stream.set_nonblocking(false)?;
+        self.check_shutdown()?;
+        Ok(stream)
+    }
+
+    /// Accepts a connection that is already waiting, or returns `None` if there is none.
+    fn accept_pending(&self) -> io::Result<Option<TcpStream>> {
+        self.check_shutdown()?;
+        let result = self.polling(|| self.listener.accept())?;
+
+        let stream = match result {
+            Ok((stream, _addr)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(None),
+            Err(err) => return Err(accept_error(err)),
+        };
+        // Accepted streams inherit the nonblocking flag on some platforms
+        stream.set_nonblocking(false)?;
+        self.check_shutdown()?;
+        Ok(Some(stream))
+    }
+
+    /// Calls `accept` with the listener switched to nonblocking mode. The listener is shared between threads,
+    /// so it only gets switched back once no other thread is polling it anymore.
+    fn polling<T, F>(&self, accept: F) -> io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        {
+            let mut polling = self.lock_polling_accepts();
+            if *polling == 0 {
+                self.listener.set_nonblocking(true)?;
+            }
+            *polling += 1;
+        }
+
+        let result = accept();
+
+        let mut polling = self.lock_polling_accepts();
+        *polling -= 1;
+        if *polling == 0 {
+            self.listener.set_nonblocking(false)?;
+        }
+        Ok(result)
+    }
+
+    /// Acquires the number of threads currently polling the listener.
+    fn lock_polling_accepts(&self) -> MutexGuard<'_, usize> {
+        self.polling_accepts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns an [`Error::Shutdown`] if the api has been shut down by a [`ShutdownHandle`].
+    fn check_shutdown(&self) -> io::Result<()> {
+        match self.shutdown.load(Ordering::SeqCst) {
+            true => Err(Error::Shutdown.into()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Reads an [`Answer`] from any reader until EOF and deserializes it.
+///
+/// This allows using the message format of the External Editor API with transports other than
+/// the TCP connections of [`ExternalEditorApi`], for example in-memory buffers or Unix sockets.
+/// Unlike [`ExternalEditorApi::read`], the size of the message isn't limited, so wrap the reader
+/// with [`Read::take`] if the source isn't trusted.
+pub fn read_from<R: Read>(mut reader: R) -> io::Result<Answer> {
+    let mut buffer = String::new();
+    reader.read_to_string(&mut buffer)?;
+    Ok(serde_json::from_str(&buffer).map_err(Error::Json)?)
+}
+
+/// Serializes a [`Message`] and writes it to any writer.
+///
+/// This allows using the message format of the External Editor API with transports other than
+/// the TCP connections of [`ExternalEditorApi`], for example in-memory buffers or Unix sockets.
+pub fn write_to<W: Write>(mut writer: W, message: &Message) -> io::Result<()> {
+    serde_json::to_writer(&mut writer, message)?;
+    writer.flush()
+}
+
+/// Rounds all numbers in the values of an [`Answer`] to `u64`, `i64` or `f64`,
+/// the same way they get parsed without the `arbitrary_precision` feature.
+#[cfg(feature = "arbitrary_precision")]
+fn lossy_numbers(answer: &mut Answer) {
+    use crate::Value;
+
+    fn round(value: &mut Value) {
+        match value {
+            Value::Number(number) => {
+                let rounded = match (number.as_u64(), number.as_i64(), number.as_f64()) {
+                    (Some(unsigned), _, _) => Some(unsigned.into()),
+                    (None, Some(signed), _) => Some(signed.into()),
+                    (None, None, float) => float.and_then(serde_json::Number::from_f64),
+                };
+                if let Some(rounded) = rounded {
+                    *number = rounded;
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(round),
+            Value::Object(values) => values.values_mut().for_each(round),
+            _ => {}
+        }
+    }
+
+    match answer {
+        Answer::AnswerCustomMessage(answer) => round(&mut answer.custom_message),
+        Answer::AnswerReturn(answer) => {
+            round(&mut answer.return_value);
+            round(&mut answer.raw_return_value);
+        }
+        _ => {}
+    }
+}
+
+/// Turns an error of accepting a connection into an [`Error::ListenerClosed`], unless the error
+/// only affects a single connection.
+fn accept_error(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset => err,
+        _ => Error::ListenerClosed(err).into(),
+    }
+}
+
+/// Returns whether messages sent to `send_ip` can arrive at a listener bound to `listen_ip`,
+/// treating unspecified and loopback addresses as the same host.
+fn same_host(listen_ip: IpAddr, send_ip: IpAddr) -> bool {
+    let local = |ip: IpAddr| ip.is_unspecified() || ip.is_loopback();
+    listen_ip == send_ip || (local(listen_ip) && local(send_ip))
+}
+
+/// Returns the time left until the `deadline`, or a [`io::ErrorKind::TimedOut`] error if it has already passed.
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(duration) if !duration.is_zero() => Ok(duration),
+        _ => Err(io::Error::from(io::ErrorKind::TimedOut)),
     }
 }
 
@@ -61,3 +725,278 @@ impl Default for ExternalEditorApi {
         Self::new()
     }
 }
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// A handle used to interrupt blocking reads of an [`ExternalEditorApi`] from another thread.
+///
+/// After calling [`ShutdownHandle::shutdown`], all current and future reads of the api return
+/// an [`Error::Shutdown`] as an [`io::Error`] of kind [`io::ErrorKind::ConnectionAborted`].
+/// The handle can be cloned and sent to other threads.
+///
+/// # Example
+/// ```no_run
+/// use std::{sync::Arc, thread};
+/// use tts_external_api::ExternalEditorApi;
+///
+/// let api = Arc::new(ExternalEditorApi::new());
+/// let handle = api.shutdown_handle().unwrap();
+///
+/// let reader = thread::spawn(move || {
+///     while let Ok(answer) = api.read() {
+///         println!("{:#?}", answer);
+///     }
+/// });
+///
+/// // Unblocks the reader thread, e.g. when the user quits the application
+/// handle.shutdown();
+/// reader.join().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// Shuts down the api and wakes up any thread that is blocked reading from it.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Connecting to the listener wakes up a blocking accept. Failing to connect means
+        // that the listener is gone, so there is nothing left to wake up.
+        let _ = TcpStream::connect(self.addr);
+    }
+
+    /// Returns whether [`ShutdownHandle::shutdown`] has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// An iterator over the answers received by an [`ExternalEditorApi`], created by [`ExternalEditorApi::incoming`].
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    api: &'a ExternalEditorApi,
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = io::Result<Answer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.api.read() {
+            Err(_) if self.api.shutdown.load(Ordering::SeqCst) => None,
+            Err(Error::ListenerClosed(_)) => None,
+            result => Some(result.map_err(io::Error::from)),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////
+
+/// How numbers in answers from Tabletop Simulator get parsed, see [`ExternalEditorApiBuilder::number_mode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    /// Numbers get parsed as `u64`, `i64` or `f64`, so integers that don't fit lose precision
+    #[default]
+    Lossy,
+    /// Numbers keep all of their digits. Requires the `arbitrary_precision` feature
+    ArbitraryPrecision,
+}
+
+/// A builder used to create an [`ExternalEditorApi`] with custom settings.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use tts_external_api::ExternalEditorApiBuilder;
+///
+/// let api = ExternalEditorApiBuilder::new()
+///     .listen_port(49998)
+///     .send_port(49999)
+///     .max_message_size(64 * 1024 * 1024)
+///     .write_timeout(Some(Duration::from_secs(1)))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExternalEditorApiBuilder {
+    listen_ip: IpAddr,
+    listen_port: u16,
+    send_ip: IpAddr,
+    send_port: u16,
+    max_message_size: usize,
+    write_timeout: Option<Duration>,
+    serialize_during_reload: bool,
+    number_mode: NumberMode,
+    trace_file: Option<PathBuf>,
+}
+
+impl ExternalEditorApiBuilder {
+    /// Constructs a new builder with the default settings.
+    pub fn new() -> Self {
+        Self {
+            listen_ip: DEFAULT_IP,
+            listen_port: DEFAULT_LISTEN_PORT,
+            send_ip: DEFAULT_IP,
+            send_port: DEFAULT_SEND_PORT,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+            serialize_during_reload: false,
+            number_mode: NumberMode::Lossy,
+            trace_file: None,
+        }
+    }
+
+    /// Sets the IP address the listener binds to.
+    ///
+    /// Some setups, mostly on Windows, resolve `localhost` to the IPv6 loopback address `::1`,
+    /// so Tabletop Simulator sends its answers there and a listener on `127.0.0.1` never receives them.
+    /// Listen on [`Ipv6Addr::LOCALHOST`](std::net::Ipv6Addr::LOCALHOST) in that case.
+    /// Defaults to [`DEFAULT_IP`] (`127.0.0.1`).
+    pub fn listen_ip(mut self, listen_ip: IpAddr) -> Self {
+        self.listen_ip = listen_ip;
+        self
+    }
+
+    /// Sets the port the listener binds to. Tabletop Simulator sends its answers to this port.
+    ///
+    /// Using port `0` binds to a free port assigned by the operating system,
+    /// which can be looked up with [`ExternalEditorApi::listen_addr`].
+    /// Defaults to [`DEFAULT_LISTEN_PORT`] (39998).
+    pub fn listen_port(mut self, listen_port: u16) -> Self {
+        self.listen_port = listen_port;
+        self
+    }
+
+    /// Sets the IP address messages get sent to. Tabletop Simulator listens for messages on this address.
+    ///
+    /// Defaults to [`DEFAULT_IP`] (`127.0.0.1`).
+    pub fn send_ip(mut self, send_ip: IpAddr) -> Self {
+        self.send_ip = send_ip;
+        self
+    }
+
+    /// Sets the port messages get sent to. Tabletop Simulator listens for messages on this port.
+    ///
+    /// Defaults to [`DEFAULT_SEND_PORT`] (39999).
+    pub fn send_port(mut self, send_port: u16) -> Self {
+        self.send_port = send_port;
+        self
+    }
+
+    /// Sets the maximum size of an incoming message in bytes.
+    /// Reading a message that exceeds this size returns an [`io::Error`] of kind [`io::ErrorKind::InvalidData`].
+    ///
+    /// Defaults to [`DEFAULT_MAX_MESSAGE_SIZE`] (16 MiB).
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets how long writing a message to Tabletop Simulator may take, or `None` to wait indefinitely.
+    /// Sending a message that takes longer returns an [`Error::SendTimeout`].
+    ///
+    /// Defaults to [`DEFAULT_WRITE_TIMEOUT`] (5 seconds).
+    pub fn write_timeout(mut self, write_timeout: Option<Duration>) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Sets whether sending a message waits for a reload that is still in progress to finish first.
+    ///
+    /// A reload started with [`ReloadMode::FireAndForget`](crate::messages::ReloadMode::FireAndForget)
+    /// is in progress until its [`AnswerReload`](crate::messages::AnswerReload) gets read. Commands that
+    /// arrive while TTS is reloading can fail with errors like "Object reference not set to an instance of an object".
+    /// With this enabled, [`ExternalEditorApi::send`] and every function built on it reads answers until the
+    /// [`AnswerReload`](crate::messages::AnswerReload) arrives before sending. The answers read in the meantime are discarded.
+    /// Defaults to `false`.
+    pub fn serialize_during_reload(mut self, serialize_during_reload: bool) -> Self {
+        self.serialize_during_reload = serialize_during_reload;
+        self
+    }
+
+    /// Sets how numbers in answers, e.g. in [`AnswerReturn::return_value`](crate::messages::AnswerReturn::return_value),
+    /// get parsed. Use [`NumberMode::ArbitraryPrecision`] to keep large integer ids intact.
+    ///
+    /// [`NumberMode::ArbitraryPrecision`] requires the `arbitrary_precision` feature of this crate,
+    /// otherwise [`ExternalEditorApiBuilder::build`] returns an error.
+    /// Defaults to [`NumberMode::Lossy`].
+    pub fn number_mode(mut self, number_mode: NumberMode) -> Self {
+        self.number_mode = number_mode;
+        self
+    }
+
+    /// Sets a file that every message sent and received gets appended to, for debugging and bug reports.
+    ///
+    /// Each message is written as a JSON line containing the `time` in seconds since the Unix epoch,
+    /// the `direction` (`"sent"` or `"received"`) and the raw `message`.
+    /// Errors while writing the trace get ignored. Defaults to no trace file.
+    pub fn trace_file(mut self, trace_file: impl Into<PathBuf>) -> Self {
+        self.trace_file = Some(trace_file.into());
+        self
+    }
+
+    /// Creates the [`ExternalEditorApi`] and binds the TcpListener to its socket address.
+    /// If the address can't be bound or the trace file can't be opened, an [`io::Error`] gets returned.
+    ///
+    /// If the listen address and the send address are the same, an [`Error::SamePort`] gets returned
+    /// as an [`io::Error`] of kind [`io::ErrorKind::InvalidInput`]. Unspecified and loopback addresses
+    /// are treated as the same address, since listening on `0.0.0.0` also receives messages sent to `127.0.0.1`.
+    /// A write timeout of zero, or [`NumberMode::ArbitraryPrecision`] without the `arbitrary_precision`
+    /// feature, returns an [`io::Error`] of the same kind.
+    pub fn build(self) -> io::Result<ExternalEditorApi> {
+        if self.listen_port == self.send_port && same_host(self.listen_ip, self.send_ip) {
+            return Err(Error::SamePort(self.listen_port).into());
+        }
+        if cfg!(not(feature = "arbitrary_precision"))
+            && self.number_mode == NumberMode::ArbitraryPrecision
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "NumberMode::ArbitraryPrecision requires the arbitrary_precision feature",
+            ));
+        }
+        if self.write_timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the write timeout must not be zero",
+            ));
+        }
+
+        let trace = match &self.trace_file {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+
+        let listener = TcpListener::bind((self.listen_ip, self.listen_port))?;
+        Ok(ExternalEditorApi {
+            listener,
+            send_addr: SocketAddr::new(self.send_ip, self.send_port),
+            max_message_size: self.max_message_size,
+            write_timeout: self.write_timeout,
+            serialize_during_reload: self.serialize_during_reload,
+            reload_pending: AtomicBool::new(false),
+            #[cfg(feature = "arbitrary_precision")]
+            number_mode: self.number_mode,
+            trace,
+            read_timeout: Mutex::new(None),
+            request_lock: Mutex::new(()),
+            next_return_id: AtomicU64::new(1),
+            abandoned_returns: Mutex::new(BTreeSet::new()),
+            polling_accepts: Mutex::new(0),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+/// Constructs a new builder with the default settings.
+/// This is functionally the same as using `ExternalEditorApiBuilder::new()`.
+impl Default for ExternalEditorApiBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,48 @@
+//! Common Tabletop Simulator types for use in custom messages and return values
+
+use serde::{Deserialize, Serialize};
+
+/// A vector with `x`, `y` and `z` components, as used for positions, rotations and scales.
+///
+/// This has the same JSON representation as a `Vector` encoded with `JSON.encode` in Lua.
+///
+/// # Example
+/// ```json
+/// { "x": 1.0, "y": 2.5, "z": -3.0 }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec3 {
+    /// X component of the vector
+    pub x: f64,
+    /// Y component of the vector
+    pub y: f64,
+    /// Z component of the vector
+    pub z: f64,
+}
+
+impl Vec3 {
+    /// Constructs a new vector from its components
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+}
+
+/// The position, rotation and scale of an object.
+///
+/// # Example
+/// ```json
+/// {
+///     "position": { "x": 0.0, "y": 1.0, "z": 0.0 },
+///     "rotation": { "x": 0.0, "y": 180.0, "z": 0.0 },
+///     "scale": { "x": 1.0, "y": 1.0, "z": 1.0 }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Transform {
+    /// Position of the object in world space
+    pub position: Vec3,
+    /// Rotation of the object in degrees
+    pub rotation: Vec3,
+    /// Scale of the object
+    pub scale: Vec3,
+}
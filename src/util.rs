@@ -0,0 +1,79 @@
+//! Utilities for exchanging data with scripts in the loaded game
+
+use crate::Value;
+use std::io;
+
+/// Characters of the standard base64 alphabet
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as a base64 [`Value::String`], so they can be sent with
+/// [`ExternalEditorApi::custom_message`](crate::ExternalEditorApi::custom_message).
+///
+/// Uses the standard base64 alphabet with padding.
+pub fn encode_binary(bytes: &[u8]) -> Value {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let block = match *chunk {
+            [a, b, c] => u32::from_be_bytes([0, a, b, c]),
+            [a, b] => u32::from_be_bytes([0, a, b, 0]),
+            [a] => u32::from_be_bytes([0, a, 0, 0]),
+            _ => unreachable!(),
+        };
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (block >> (18 - 6 * index)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    Value::String(encoded)
+}
+
+/// Decodes a base64 [`Value::String`] created by [`encode_binary`], for example from an
+/// [`AnswerCustomMessage`](crate::messages::AnswerCustomMessage), back into bytes.
+///
+/// If the value isn't a string of valid base64, an [`io::Error`] of kind [`io::ErrorKind::InvalidData`] gets returned.
+pub fn decode_binary(value: &Value) -> io::Result<Vec<u8>> {
+    let encoded = value
+        .as_str()
+        .ok_or_else(|| invalid_base64("expected a string"))?
+        .as_bytes();
+    if encoded.len() % 4 != 0 {
+        return Err(invalid_base64("length is not a multiple of 4"));
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let chunks = encoded.len() / 4;
+    for (index, chunk) in encoded.chunks(4).enumerate() {
+        // Padding is only allowed at the end of the last chunk
+        let padding = match chunk {
+            [_, _, b'=', b'='] if index + 1 == chunks => 2,
+            [_, _, _, b'='] if index + 1 == chunks => 1,
+            _ => 0,
+        };
+
+        let mut block = 0;
+        for &char in &chunk[..4 - padding] {
+            let sextet = BASE64_ALPHABET
+                .iter()
+                .position(|&c| c == char)
+                .ok_or_else(|| invalid_base64("invalid character"))?;
+            block = (block << 6) | sextet as u32;
+        }
+        block <<= 6 * padding;
+
+        bytes.extend_from_slice(&block.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(bytes)
+}
+
+/// Creates the error returned by [`decode_binary`] for malformed input.
+fn invalid_base64(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid base64: {reason}"),
+    )
+}